@@ -0,0 +1,139 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// A single property of an `assert` operation that did not hold.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Mismatch {
+    pub property: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// An `assert` operation did not match the filesystem.
+///
+/// Surfaces as exit code `2` (see `exit_codes`).
+#[derive(Debug, thiserror::Error)]
+#[error("assertion failed for {}: {}", path.display(), describe(mismatches))]
+pub struct AssertionFailed {
+    pub path: PathBuf,
+    pub mismatches: Vec<Mismatch>,
+}
+
+fn describe(mismatches: &[Mismatch]) -> String {
+    mismatches
+        .iter()
+        .map(|m| format!("{} expected {}, found {}", m.property, m.expected, m.actual))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Evaluate an `assert` operation, failing with `AssertionFailed` on mismatch.
+pub fn check(root: &Path, op: &crate::model::Operation) -> Result<()> {
+    let crate::model::Operation::Assert { path, .. } = op else {
+        anyhow::bail!("not an assert operation");
+    };
+    let entry = crate::resolve::resolve_entry(root, path)?;
+    let mismatches = evaluate(&entry, op)?;
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(AssertionFailed {
+            path: entry,
+            mismatches,
+        }
+        .into())
+    }
+}
+
+fn evaluate(entry: &Path, op: &crate::model::Operation) -> Result<Vec<Mismatch>> {
+    let crate::model::Operation::Assert {
+        exists,
+        kind,
+        size,
+        sha256,
+        blake3,
+        mode,
+        ..
+    } = op
+    else {
+        anyhow::bail!("not an assert operation");
+    };
+    let mut mismatches = Vec::new();
+    let mut mismatch = |property: &str, expected: String, actual: String| {
+        mismatches.push(Mismatch {
+            property: property.to_string(),
+            expected,
+            actual,
+        });
+    };
+
+    let Ok(link_metadata) = std::fs::symlink_metadata(entry) else {
+        if *exists {
+            mismatch("exists", "true".to_string(), "false".to_string());
+        }
+        return Ok(mismatches);
+    };
+    if !*exists {
+        mismatch("exists", "false".to_string(), "true".to_string());
+        return Ok(mismatches);
+    }
+
+    let file_type = link_metadata.file_type();
+    let actual_kind = if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "dir"
+    } else if file_type.is_file() {
+        "file"
+    } else {
+        "other"
+    };
+    if let Some(kind) = kind {
+        let expected = match kind {
+            crate::model::EntryKind::File => "file",
+            crate::model::EntryKind::Dir => "dir",
+            crate::model::EntryKind::Symlink => "symlink",
+        };
+        if expected != actual_kind {
+            mismatch("kind", expected.to_string(), actual_kind.to_string());
+        }
+    }
+
+    if size.is_none() && mode.is_none() && sha256.is_none() && blake3.is_none() {
+        return Ok(mismatches);
+    }
+    // Remaining properties describe what the entry points to.
+    let metadata = std::fs::metadata(entry)?;
+    if let Some(size) = size
+        && *size != metadata.len()
+    {
+        mismatch("size", size.to_string(), metadata.len().to_string());
+    }
+    if let Some(mode) = mode {
+        let actual = crate::fsops::read_mode(entry)?;
+        if *mode != actual {
+            mismatch("mode", String::from(*mode), String::from(actual));
+        }
+    }
+    if sha256.is_some() || blake3.is_some() {
+        if !metadata.is_file() {
+            anyhow::bail!("cannot hash a non-regular file: {}", entry.display());
+        }
+        let contents = std::fs::read(entry)?;
+        if let Some(expected) = sha256 {
+            use sha2::Digest;
+            let actual = hex::encode(sha2::Sha256::digest(&contents));
+            if !expected.eq_ignore_ascii_case(&actual) {
+                mismatch("sha256", expected.clone(), actual);
+            }
+        }
+        if let Some(expected) = blake3 {
+            let actual = crate::fsops::content_hash(&contents);
+            if !expected.eq_ignore_ascii_case(&actual) {
+                mismatch("blake3", expected.clone(), actual);
+            }
+        }
+    }
+    Ok(mismatches)
+}
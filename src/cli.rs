@@ -11,20 +11,31 @@ pub struct Cli {
 
 #[derive(Subcommand)]
 pub enum Command {
-    /// Print JSON Schema for manifests.
-    Schema,
+    /// Print JSON Schema for manifests (JSON, YAML or TOML).
+    Schema(SchemaArgs),
     /// Validate, preview, or apply a filesystem transaction.
     Apply(ApplyArgs),
     /// Undo a previously applied transaction using its journal.
     Undo(UndoArgs),
 }
 
+#[derive(Args)]
+pub struct SchemaArgs {
+    /// Manifest version to print the schema for (default: current).
+    #[arg(long)]
+    pub version: Option<u32>,
+}
+
 #[derive(Args)]
 pub struct ApplyArgs {
-    /// Path to manifest JSON file.
+    /// Path to manifest file (JSON, YAML, TOML or NDJSON), or `-` for stdin.
     #[arg(long, required = true)]
     pub manifest: PathBuf,
 
+    /// Manifest format (default: from the file extension, else JSON).
+    #[arg(long)]
+    pub format: Option<crate::model::ManifestFormat>,
+
     /// Only validate manifest, do not execute.
     #[arg(long)]
     pub validate_only: bool,
@@ -0,0 +1,931 @@
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+use std::path::Path;
+
+/// Kind of manifest problem, stable for agents to match on.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Code {
+    /// The document is not valid JSON, YAML or TOML.
+    Syntax,
+    /// `version` is newer than this `tfs` understands.
+    UnsupportedVersion,
+    /// A plan setting or operation field has the wrong type or value.
+    InvalidField,
+    /// A required field is missing.
+    MissingField,
+    /// `op` names no known operation.
+    UnknownOperation,
+    /// An operation is malformed or violates a constraint.
+    InvalidOperation,
+    /// Two operations share an `id`.
+    DuplicateId,
+    /// `after` names an id no operation has.
+    UnknownDependency,
+    /// Operations depend on each other in a cycle.
+    DependencyCycle,
+    /// `root` is not absolute.
+    RelativeRoot,
+    /// An operation path resolves outside `root`.
+    EscapesRoot,
+    /// A `${name}` reference cannot be substituted.
+    InvalidVariable,
+    /// An included manifest cannot be loaded.
+    InvalidInclude,
+}
+
+impl Code {
+    fn hint(self) -> &'static str {
+        match self {
+            Code::Syntax => "fix the syntax at this position",
+            Code::UnsupportedVersion => {
+                "upgrade tfs, or generate the manifest for a supported version"
+            }
+            Code::InvalidField => "see `tfs schema` for the allowed values",
+            Code::MissingField => "add the missing field",
+            Code::UnknownOperation => "use one of the operations listed by `tfs schema`",
+            Code::InvalidOperation => "fix the operation; see `tfs schema` for its fields",
+            Code::DuplicateId => "give every operation a unique `id`",
+            Code::UnknownDependency => "list only ids of operations in this manifest",
+            Code::DependencyCycle => "remove one of the `after` entries that form the cycle",
+            Code::RelativeRoot => "use an absolute `root`, or pass `--root`",
+            Code::EscapesRoot => "keep every path under `root`, including through symlinks",
+            Code::InvalidVariable => "define every referenced variable in `vars`, without cycles",
+            Code::InvalidInclude => {
+                "check the include path; included manifests must not set `root` or include each other in a cycle"
+            }
+        }
+    }
+}
+
+/// A single problem in a manifest.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: Code,
+    /// JSON pointer to the offending value (`""` is the whole manifest).
+    pub pointer: String,
+    /// 1-based line, when the position is known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// 1-based column, when the position is known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    pub message: String,
+    pub hint: String,
+}
+
+impl Diagnostic {
+    fn new(code: Code, pointer: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            pointer: pointer.into(),
+            line: None,
+            column: None,
+            message: message.into(),
+            hint: code.hint().to_string(),
+        }
+    }
+
+    fn at(mut self, (line, column): (usize, usize)) -> Self {
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+
+    /// Position the diagnostic at its pointer, when that can be known.
+    fn placed(self, position: Locate) -> Self {
+        match position(&self.pointer) {
+            Some(at) => self.at(at),
+            None => self,
+        }
+    }
+}
+
+/// A manifest failed to load or validate; lists every problem found.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid manifest:\n{}", describe(diagnostics))]
+pub struct InvalidManifest {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+fn describe(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|d| {
+            let position = match (d.line, d.column) {
+                (Some(line), Some(column)) => format!(" (line {}, column {})", line, column),
+                _ => String::new(),
+            };
+            format!(
+                "  {}{}: {} [{}]; {}",
+                if d.pointer.is_empty() {
+                    "/"
+                } else {
+                    &d.pointer
+                },
+                position,
+                d.message,
+                serde_json::to_value(d.code)
+                    .ok()
+                    .and_then(|v| v.as_str().map(str::to_string))
+                    .unwrap_or_default(),
+                d.hint
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Position of a JSON pointer in the manifest text, when it can be known.
+type Locate<'a> = &'a dyn Fn(&str) -> Option<(usize, usize)>;
+
+/// Check a whole manifest, collecting every problem instead of stopping at
+/// the first.
+///
+/// `manifest` is where the text came from, for resolving includes. `root` is
+/// the `--root` override, if any. Positions are reported for JSON and NDJSON,
+/// and for YAML and TOML syntax errors.
+pub fn check(
+    text: &str,
+    format: crate::model::ManifestFormat,
+    manifest: &Path,
+    root: Option<&Path>,
+) -> Vec<Diagnostic> {
+    use crate::model::ManifestFormat;
+
+    let document: serde_json::Value = match format {
+        ManifestFormat::Json => match serde_json::from_str(text) {
+            Ok(value) => value,
+            Err(e) => {
+                return vec![
+                    Diagnostic::new(Code::Syntax, "", e.to_string()).at((e.line(), e.column())),
+                ];
+            }
+        },
+        ManifestFormat::Yaml => match crate::model::from_yaml(text) {
+            Ok(value) => value,
+            Err(e) => {
+                let diagnostic = Diagnostic::new(Code::Syntax, "", e.to_string());
+                return vec![match e.location() {
+                    Some(location) => {
+                        diagnostic.at((location.line() as usize, location.column() as usize))
+                    }
+                    None => diagnostic,
+                }];
+            }
+        },
+        ManifestFormat::Toml => match toml::from_str(text) {
+            Ok(value) => value,
+            Err(e) => {
+                let diagnostic = Diagnostic::new(Code::Syntax, "", e.message());
+                return vec![match e.span() {
+                    Some(span) => diagnostic.at(line_column(text, span.start)),
+                    None => diagnostic,
+                }];
+            }
+        },
+        ManifestFormat::Ndjson => {
+            let reader = std::io::Cursor::new(text.to_string());
+            return check_ndjson(Box::new(reader), manifest, root);
+        }
+    };
+
+    let in_text = |pointer: &str| locate(text, pointer);
+    let nowhere = |_: &str| None;
+    let position: Locate = if format == ManifestFormat::Json {
+        &in_text
+    } else {
+        &nowhere
+    };
+    let Some(settings) = document.as_object() else {
+        return vec![
+            Diagnostic::new(Code::InvalidField, "", "manifest must be an object").placed(position),
+        ];
+    };
+    let (checker, mut diagnostics) = Checker::new(settings, manifest, root, false, position);
+    let Some(mut checker) = checker else {
+        return diagnostics;
+    };
+    match settings.get("operations").map(serde_json::Value::as_array) {
+        None => diagnostics.push(Diagnostic::new(
+            Code::MissingField,
+            "",
+            "missing field `operations`",
+        )),
+        Some(None) => diagnostics.push(
+            Diagnostic::new(
+                Code::InvalidField,
+                "/operations",
+                "operations must be an array",
+            )
+            .placed(position),
+        ),
+        Some(Some(operations)) => {
+            for (index, value) in operations.iter().enumerate() {
+                let pointer = format!("/operations/{}", index);
+                diagnostics.extend(checker.operation(value, &pointer, position));
+            }
+        }
+    }
+    diagnostics.extend(checker.finish());
+    diagnostics
+}
+
+/// Check an NDJSON manifest line by line, collecting every problem.
+///
+/// Operations are numbered from 0 after the header line, so
+/// `/operations/3/dst` is the `dst` of the fourth operation line. Only their
+/// ids are kept, unless the manifest runs as a whole plan.
+pub fn check_ndjson(
+    reader: Box<dyn std::io::BufRead>,
+    manifest: &Path,
+    root: Option<&Path>,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut checker: Option<Checker> = None;
+    let mut index = 0;
+    for (number, text) in reader.lines().enumerate() {
+        let number = number + 1;
+        let text = match text {
+            Ok(text) => text,
+            Err(e) => {
+                diagnostics.push(Diagnostic::new(Code::Syntax, "", e.to_string()).at((number, 1)));
+                return diagnostics;
+            }
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+        let value: serde_json::Value = match serde_json::from_str(&text) {
+            Ok(value) => value,
+            Err(e) => {
+                let pointer = match checker {
+                    Some(_) => format!("/operations/{}", index),
+                    None => String::new(),
+                };
+                diagnostics.push(
+                    Diagnostic::new(Code::Syntax, pointer, e.to_string()).at((number, e.column())),
+                );
+                if checker.is_none() {
+                    return diagnostics;
+                }
+                index += 1;
+                continue;
+            }
+        };
+        match &mut checker {
+            None => {
+                let position =
+                    |pointer: &str| locate(&text, pointer).map(|(_, column)| (number, column));
+                let Some(settings) = value.as_object() else {
+                    diagnostics.push(
+                        Diagnostic::new(Code::InvalidField, "", "NDJSON header must be an object")
+                            .at((number, 1)),
+                    );
+                    return diagnostics;
+                };
+                if settings.contains_key("operations") {
+                    diagnostics.push(
+                        Diagnostic::new(
+                            Code::InvalidField,
+                            "/operations",
+                            "NDJSON header must not contain operations",
+                        )
+                        .placed(&position),
+                    );
+                }
+                let (header, found) = Checker::new(settings, manifest, root, true, &position);
+                diagnostics.extend(found);
+                match header {
+                    Some(header) => checker = Some(header),
+                    None => return diagnostics,
+                }
+            }
+            Some(checker) => {
+                let pointer = format!("/operations/{}", index);
+                let position = |p: &str| {
+                    locate(&text, p.strip_prefix(pointer.as_str())?)
+                        .map(|(_, column)| (number, column))
+                };
+                diagnostics.extend(checker.operation(&value, &pointer, &position));
+                index += 1;
+            }
+        }
+    }
+    match checker {
+        Some(checker) => diagnostics.extend(checker.finish()),
+        None => diagnostics.push(Diagnostic::new(
+            Code::MissingField,
+            "",
+            "NDJSON manifest has no header line",
+        )),
+    }
+    diagnostics
+}
+
+/// Checks shared by whole and streamed manifests, fed one operation at a
+/// time after the plan settings.
+struct Checker {
+    /// Plan settings, when they are valid.
+    plan: Option<crate::model::Plan>,
+    vars: Option<std::collections::BTreeMap<String, String>>,
+    /// Ids of the operations checked so far, included ones first.
+    ids: std::collections::HashSet<String>,
+    /// Whether `after` may name later operations; streamed `op` manifests
+    /// may only run after earlier ones.
+    forward: bool,
+    /// `after` references to ids not seen yet, resolved by `finish`.
+    pending: Vec<(String, Diagnostic)>,
+    /// Every operation, for the dependency cycle check; `None` when streamed.
+    operations: Option<Vec<crate::model::Operation>>,
+    /// Number of operations checked so far, included ones first.
+    count: usize,
+    problems: usize,
+}
+
+impl Checker {
+    /// Check the plan settings in `settings` (`operations` is ignored).
+    /// Returns no checker when the version is unsupported, since a newer
+    /// manifest may have any shape.
+    fn new(
+        settings: &serde_json::Map<String, serde_json::Value>,
+        manifest: &Path,
+        root: Option<&Path>,
+        ndjson: bool,
+        position: Locate,
+    ) -> (Option<Self>, Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+        if let Some(version) = settings.get("version") {
+            let problem = match version.as_u64().and_then(|v| u32::try_from(v).ok()) {
+                Some(version) => crate::model::check_version(version)
+                    .err()
+                    .map(|e| Diagnostic::new(Code::UnsupportedVersion, "/version", e.to_string())),
+                None => Some(Diagnostic::new(
+                    Code::InvalidField,
+                    "/version",
+                    "version must be a positive integer",
+                )),
+            };
+            if let Some(problem) = problem {
+                diagnostics.push(problem.placed(position));
+                return (None, diagnostics);
+            }
+        }
+
+        let mut header = settings.clone();
+        header.insert(
+            "operations".to_string(),
+            serde_json::Value::Array(Vec::new()),
+        );
+        let plan: Option<crate::model::Plan> =
+            match serde_path_to_error::deserialize(serde_json::Value::Object(header)) {
+                Ok(plan) => Some(plan),
+                Err(e) => {
+                    let pointer = pointer_for(e.path());
+                    let message = e.inner().to_string();
+                    let code = if message.starts_with("missing field") {
+                        Code::MissingField
+                    } else {
+                        Code::InvalidField
+                    };
+                    diagnostics.push(Diagnostic::new(code, pointer, message).placed(position));
+                    None
+                }
+            };
+
+        let mut checker = Self {
+            plan: None,
+            vars: None,
+            ids: std::collections::HashSet::new(),
+            forward: true,
+            pending: Vec::new(),
+            operations: None,
+            count: 0,
+            problems: 0,
+        };
+        if let Some(mut plan) = plan {
+            if root.is_none() && !plan.root.is_absolute() {
+                diagnostics.push(
+                    Diagnostic::new(Code::RelativeRoot, "/root", "root must be an absolute path")
+                        .placed(position),
+                );
+            }
+            checker.forward = !ndjson || plan.transaction != crate::model::TransactionMode::Op;
+            match plan.resolved_vars() {
+                Ok(vars) => checker.vars = Some(vars),
+                Err(e) => diagnostics.push(
+                    Diagnostic::new(Code::InvalidVariable, "/vars", e.to_string()).placed(position),
+                ),
+            }
+            // Included operations come first; their problems are reported
+            // against `include`.
+            let mut included = Vec::new();
+            if let Err(e) = plan.resolve_includes(manifest) {
+                diagnostics.push(
+                    Diagnostic::new(Code::InvalidInclude, "/include", format!("{:#}", e))
+                        .placed(position),
+                );
+            } else {
+                included = std::mem::take(&mut plan.operations);
+            }
+            checker.operations = checker.forward.then(Vec::new);
+            for mut op in included {
+                let problem = match op.validate() {
+                    Err(e) => Some((Code::InvalidOperation, e)),
+                    Ok(()) => match &checker.vars {
+                        Some(vars) => op
+                            .expand_vars(vars)
+                            .err()
+                            .map(|e| (Code::InvalidVariable, e)),
+                        None => None,
+                    },
+                };
+                if let Some((code, e)) = problem {
+                    diagnostics.push(
+                        Diagnostic::new(code, "/include", format!("{:#}", e)).placed(position),
+                    );
+                }
+                if let Some(id) = &op.meta().id {
+                    checker.ids.insert(id.clone());
+                }
+                checker.count += 1;
+                if let Some(operations) = &mut checker.operations {
+                    operations.push(op);
+                }
+            }
+            if let Some(root) = root {
+                plan.root = root.to_path_buf();
+            }
+            checker.plan = Some(plan);
+        }
+        checker.problems = diagnostics.len();
+        (Some(checker), diagnostics)
+    }
+
+    /// Check the operation `value` found at `pointer`.
+    fn operation(
+        &mut self,
+        value: &serde_json::Value,
+        pointer: &str,
+        position: Locate,
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        self.count += 1;
+        let mut op: crate::model::Operation = match serde_json::from_value(value.clone()) {
+            Ok(op) => op,
+            Err(e) => {
+                let message = e.to_string();
+                let (code, pointer) = if unknown_operation(value) {
+                    (Code::UnknownOperation, format!("{}/op", pointer))
+                } else if message.starts_with("missing field") {
+                    (Code::MissingField, pointer.to_string())
+                } else if let Some(field) = failing_field(value, &message) {
+                    (Code::InvalidField, format!("{}{}", pointer, field))
+                } else {
+                    (Code::InvalidOperation, pointer.to_string())
+                };
+                self.problems += 1;
+                return vec![Diagnostic::new(code, pointer, message).placed(position)];
+            }
+        };
+        if let Err(e) = op.validate() {
+            let pointer = match e.downcast_ref::<crate::model::InvalidOpField>() {
+                Some(invalid) => format!("{}/{}", pointer, invalid.field),
+                None => pointer.to_string(),
+            };
+            diagnostics.push(Diagnostic::new(
+                Code::InvalidOperation,
+                pointer,
+                format!("{:#}", e),
+            ));
+        }
+        let name = match &op.meta().id {
+            Some(id) => id.clone(),
+            None => format!("#{}", self.count),
+        };
+        for (index, after) in op.meta().after.iter().enumerate() {
+            if self.ids.contains(after) {
+                continue;
+            }
+            let problem = Diagnostic::new(
+                Code::UnknownDependency,
+                format!("{}/after/{}", pointer, index),
+                format!("unknown dependency {:?} of {}", after, name),
+            );
+            if self.forward {
+                self.pending.push((after.clone(), problem.placed(position)));
+            } else {
+                diagnostics.push(Diagnostic {
+                    message: format!(
+                        "{} (streamed operations may only run after earlier ones)",
+                        problem.message
+                    ),
+                    ..problem
+                });
+            }
+        }
+        if let Some(id) = &op.meta().id
+            && !id.is_empty()
+            && !self.ids.insert(id.clone())
+        {
+            diagnostics.push(Diagnostic::new(
+                Code::DuplicateId,
+                format!("{}/id", pointer),
+                format!("duplicate operation id: {}", id),
+            ));
+        }
+        let expanded = match &self.vars {
+            Some(vars) => match op.expand_vars(vars) {
+                Ok(()) => true,
+                Err(e) => {
+                    diagnostics.push(Diagnostic::new(
+                        Code::InvalidVariable,
+                        pointer,
+                        format!("{:#}", e),
+                    ));
+                    false
+                }
+            },
+            None => false,
+        };
+        // Confinement needs the expanded paths and an existing root to
+        // resolve them against.
+        if let Some(plan) = &self.plan
+            && expanded
+            && plan.root.is_absolute()
+            && plan.root.is_dir()
+        {
+            for (field, path) in op.named_paths() {
+                if let Err(e) = crate::resolve::resolve_path(&plan.root, path) {
+                    diagnostics.push(Diagnostic::new(
+                        Code::EscapesRoot,
+                        format!("{}/{}", pointer, field),
+                        e.to_string(),
+                    ));
+                }
+            }
+        }
+        if let Some(operations) = &mut self.operations {
+            operations.push(op);
+        }
+        self.problems += diagnostics.len();
+        diagnostics
+            .into_iter()
+            .map(|d| d.placed(position))
+            .collect()
+    }
+
+    /// Report `after` references that never matched an id and, when nothing
+    /// else is wrong, dependency cycles.
+    fn finish(self) -> Vec<Diagnostic> {
+        let diagnostics: Vec<Diagnostic> = self
+            .pending
+            .into_iter()
+            .filter(|(id, _)| !self.ids.contains(id))
+            .map(|(_, diagnostic)| diagnostic)
+            .collect();
+        if self.problems > 0 || !diagnostics.is_empty() {
+            return diagnostics;
+        }
+        if let (Some(plan), Some(operations)) = (self.plan, self.operations) {
+            let plan = crate::model::Plan { operations, ..plan };
+            if let Err(e) = crate::validate::execution_order(&plan)
+                && e.to_string().starts_with("dependency cycle")
+            {
+                return vec![Diagnostic::new(
+                    Code::DependencyCycle,
+                    "/operations",
+                    e.to_string(),
+                )];
+            }
+        }
+        diagnostics
+    }
+}
+
+/// Whether the `op` of the operation `value` names no known operation.
+fn unknown_operation(value: &serde_json::Value) -> bool {
+    let Some(op) = value.get("op") else {
+        return false;
+    };
+    serde_json::from_value::<crate::model::Operation>(serde_json::json!({ "op": op }))
+        .is_err_and(|e| e.to_string().starts_with("unknown variant"))
+}
+
+/// Pointer, relative to the operation, to the field of `value` that fails
+/// to deserialize with `message`.
+///
+/// Operations are internally tagged, so serde reports no path. The shared
+/// (flattened) fields are checked on their own; any other field is found by
+/// leaving each out in turn until the error changes.
+fn failing_field(value: &serde_json::Value, message: &str) -> Option<String> {
+    let object = value.as_object()?;
+    if let Err(e) = serde_path_to_error::deserialize::<_, crate::model::OpMeta>(value) {
+        return Some(pointer_for(e.path()));
+    }
+    if let Err(e) = serde_path_to_error::deserialize::<_, crate::model::OpOverrides>(value) {
+        return Some(pointer_for(e.path()));
+    }
+    object.keys().filter(|key| *key != "op").find_map(|key| {
+        let mut without = object.clone();
+        without.remove(key);
+        match serde_json::from_value::<crate::model::Operation>(without.into()) {
+            Err(e) if e.to_string() == message => None,
+            _ => Some(pointer_for_key(key)),
+        }
+    })
+}
+
+/// JSON pointer for a single member `key`.
+fn pointer_for_key(key: &str) -> String {
+    format!("/{}", key.replace('~', "~0").replace('/', "~1"))
+}
+
+/// JSON pointer for a serde path (e.g. `vars.a` becomes `/vars/a`).
+fn pointer_for(path: &serde_path_to_error::Path) -> String {
+    use serde_path_to_error::Segment;
+    path.iter()
+        .filter_map(|segment| match segment {
+            Segment::Map { key } => Some(pointer_for_key(key)),
+            Segment::Seq { index } => Some(format!("/{}", index)),
+            Segment::Enum { .. } | Segment::Unknown => None,
+        })
+        .collect()
+}
+
+/// 1-based line and column of the value `pointer` refers to in JSON `text`.
+pub fn locate(text: &str, pointer: &str) -> Option<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut pos = skip_whitespace(bytes, 0);
+    if !pointer.is_empty() {
+        for token in pointer.strip_prefix('/')?.split('/') {
+            let token = token.replace("~1", "/").replace("~0", "~");
+            pos = match bytes.get(pos)? {
+                b'{' => find_member(bytes, pos, &token)?,
+                b'[' => find_element(bytes, pos, token.parse().ok()?)?,
+                _ => return None,
+            };
+        }
+    }
+    Some(line_column(text, pos))
+}
+
+/// Position of the value of member `key` in the object starting at `pos`.
+fn find_member(bytes: &[u8], pos: usize, key: &str) -> Option<usize> {
+    let mut pos = skip_whitespace(bytes, pos + 1);
+    while *bytes.get(pos)? == b'"' {
+        let end = skip_string(bytes, pos)?;
+        let name: String = serde_json::from_slice(&bytes[pos..end]).ok()?;
+        pos = skip_whitespace(bytes, end);
+        if *bytes.get(pos)? != b':' {
+            return None;
+        }
+        pos = skip_whitespace(bytes, pos + 1);
+        if name == key {
+            return Some(pos);
+        }
+        pos = skip_whitespace(bytes, skip_value(bytes, pos)?);
+        if *bytes.get(pos)? != b',' {
+            return None;
+        }
+        pos = skip_whitespace(bytes, pos + 1);
+    }
+    None
+}
+
+/// Position of element `index` in the array starting at `pos`.
+fn find_element(bytes: &[u8], pos: usize, index: usize) -> Option<usize> {
+    let mut pos = skip_whitespace(bytes, pos + 1);
+    for _ in 0..index {
+        if *bytes.get(pos)? == b']' {
+            return None;
+        }
+        pos = skip_whitespace(bytes, skip_value(bytes, pos)?);
+        if *bytes.get(pos)? != b',' {
+            return None;
+        }
+        pos = skip_whitespace(bytes, pos + 1);
+    }
+    (*bytes.get(pos)? != b']').then_some(pos)
+}
+
+fn skip_whitespace(bytes: &[u8], mut pos: usize) -> usize {
+    while bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+        pos += 1;
+    }
+    pos
+}
+
+/// End (exclusive) of the string starting at `pos`.
+fn skip_string(bytes: &[u8], pos: usize) -> Option<usize> {
+    let mut pos = pos + 1;
+    loop {
+        match bytes.get(pos)? {
+            b'\\' => pos += 2,
+            b'"' => return Some(pos + 1),
+            _ => pos += 1,
+        }
+    }
+}
+
+/// End (exclusive) of the value starting at `pos`.
+fn skip_value(bytes: &[u8], pos: usize) -> Option<usize> {
+    match bytes.get(pos)? {
+        b'"' => skip_string(bytes, pos),
+        b'{' | b'[' => {
+            let mut depth = 0;
+            let mut pos = pos;
+            loop {
+                match bytes.get(pos)? {
+                    b'"' => {
+                        pos = skip_string(bytes, pos)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(pos + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                pos += 1;
+            }
+        }
+        _ => {
+            let mut pos = pos;
+            while bytes
+                .get(pos)
+                .is_some_and(|b| !matches!(b, b',' | b'}' | b']') && !b.is_ascii_whitespace())
+            {
+                pos += 1;
+            }
+            Some(pos)
+        }
+    }
+}
+
+/// 1-based line and column (in characters) of byte `offset` in `text`.
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |line| line.chars().count())
+        + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locate_finds_nested_values() {
+        let text = "{\n  \"root\": \"/r\",\n  \"operations\": [\n    { \"op\": \"mkdir\", \"dst\": \"a\" },\n    { \"op\": \"move\", \"src\": \"b\\\"]\", \"dst\": \"c\" }\n  ]\n}";
+        assert_eq!(locate(text, ""), Some((1, 1)));
+        assert_eq!(locate(text, "/root"), Some((2, 11)));
+        assert_eq!(locate(text, "/operations/1"), Some((5, 5)));
+        assert_eq!(locate(text, "/operations/1/dst"), Some((5, 43)));
+        assert_eq!(locate(text, "/operations/2"), None);
+        assert_eq!(locate(text, "/missing"), None);
+    }
+
+    #[test]
+    fn check_collects_every_problem() {
+        let text = r#"{
+  "root": "relative",
+  "collision_policy": "sometimes",
+  "operations": [
+    { "op": "mkdir", "dst": "a", "id": "x" },
+    { "op": "teleport", "dst": "b" },
+    { "op": "move", "src": "a" },
+    { "op": "chown", "dst": "a", "id": "x", "after": ["nope"] }
+  ]
+}"#;
+        let diagnostics = check(
+            text,
+            crate::model::ManifestFormat::Json,
+            Path::new("-"),
+            None,
+        );
+        let found: Vec<(Code, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code, d.pointer.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Code::InvalidField, "/collision_policy"),
+                (Code::UnknownOperation, "/operations/1/op"),
+                (Code::MissingField, "/operations/2"),
+                (Code::InvalidOperation, "/operations/3"),
+                (Code::DuplicateId, "/operations/3/id"),
+                (Code::UnknownDependency, "/operations/3/after/0"),
+            ]
+        );
+        assert_eq!(
+            (diagnostics[1].line, diagnostics[1].column),
+            (Some(6), Some(13))
+        );
+        assert!(diagnostics.iter().all(|d| !d.hint.is_empty()));
+
+        // The relative root is reported once the settings parse.
+        let diagnostics = check(
+            r#"{ "root": "relative", "operations": [] }"#,
+            crate::model::ManifestFormat::Json,
+            Path::new("-"),
+            None,
+        );
+        assert_eq!(diagnostics[0].code, Code::RelativeRoot);
+        assert_eq!(diagnostics[0].column, Some(11));
+    }
+
+    #[test]
+    fn check_points_at_operation_fields() {
+        let root = tempfile::tempdir().unwrap();
+        let text = serde_json::json!({
+            "root": root.path(),
+            "operations": [
+                { "op": "mkdir", "dst": 5 },
+                { "op": "mkdir", "dst": "a", "after": ["b", 7] },
+                { "op": "mkdir", "dst": "a", "collision_policy": "sometimes" },
+                { "op": "move_into", "srcs": ["a", "."], "dir": "d" },
+                { "op": "move", "src": "a", "dst": "b", "cross_device": true, "allow_cross_device": false },
+                { "op": "copy", "src": "a", "dst": "../outside" },
+                { "op": "symlink", "target": "/etc/passwd", "dst": "link" }
+            ]
+        })
+        .to_string();
+        let diagnostics = check(
+            &text,
+            crate::model::ManifestFormat::Json,
+            Path::new("-"),
+            None,
+        );
+        let found: Vec<(Code, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code, d.pointer.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Code::InvalidField, "/operations/0/dst"),
+                (Code::InvalidField, "/operations/1/after/1"),
+                (Code::InvalidField, "/operations/2/collision_policy"),
+                (Code::InvalidOperation, "/operations/3/srcs/1"),
+                (Code::InvalidOperation, "/operations/4/allow_cross_device"),
+                (Code::EscapesRoot, "/operations/5/dst"),
+                (Code::EscapesRoot, "/operations/6/target"),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_ndjson_and_dependency_cycles() {
+        use crate::model::ManifestFormat;
+
+        // Streamed `op` manifests may only depend on earlier operations.
+        let text = concat!(
+            "{ \"root\": \"/r\", \"transaction\": \"op\" }\n",
+            "\n",
+            "{ \"op\": \"mkdir\", \"dst\": \"a\", \"after\": [\"b\"] }\n",
+            "{ \"op\": \"mkdir\", \"dst\": \"b\", \"id\": \"b\" }\n",
+            "not json\n",
+        );
+        let diagnostics = check(text, ManifestFormat::Ndjson, Path::new("-"), None);
+        let found: Vec<(Code, &str, Option<usize>, Option<usize>)> = diagnostics
+            .iter()
+            .map(|d| (d.code, d.pointer.as_str(), d.line, d.column))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    Code::UnknownDependency,
+                    "/operations/0/after/0",
+                    Some(3),
+                    Some(40)
+                ),
+                (Code::Syntax, "/operations/2", Some(5), Some(2)),
+            ]
+        );
+
+        // Whole plans may name later operations, but not in a cycle.
+        let root = tempfile::tempdir().unwrap();
+        let text = serde_json::json!({
+            "root": root.path(),
+            "operations": [
+                { "op": "mkdir", "dst": "a", "id": "a", "after": ["b"] },
+                { "op": "mkdir", "dst": "b", "id": "b", "after": ["a"] }
+            ]
+        })
+        .to_string();
+        let diagnostics = check(&text, ManifestFormat::Json, Path::new("-"), None);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Code::DependencyCycle);
+        assert!(diagnostics[0].message.contains("a, b"));
+    }
+}
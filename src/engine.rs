@@ -1,4 +1,5 @@
 use crate::cli::{ApplyArgs, UndoArgs};
+use crate::diagnostics;
 use crate::exit_codes::exit;
 use crate::journal::JournalWriter;
 use crate::model;
@@ -12,14 +13,36 @@ pub fn apply(args: ApplyArgs) -> Result<i32> {
     let mut reporter = Reporter::new(args.json);
 
     // Load and validate plan
-    let mut plan = model::load_plan(&args.manifest).context("failed to load manifest")?;
-    if let Some(root) = args.root {
-        plan.root = root;
-    }
-    if let Some(collision_policy) = args.collision_policy {
-        plan.collision_policy = collision_policy;
-    }
-    plan.allow_overwrite = args.allow_overwrite;
+    let format = args
+        .format
+        .unwrap_or_else(|| model::ManifestFormat::from_path(&args.manifest));
+    let mut plan = if format == model::ManifestFormat::Ndjson {
+        // Standard input cannot be read twice, so only files are checked
+        // ahead of streaming.
+        if args.manifest != std::path::Path::new("-") {
+            let file = std::fs::File::open(&args.manifest).context("failed to load manifest")?;
+            let reader = Box::new(std::io::BufReader::new(file));
+            report_diagnostics(
+                &mut reporter,
+                diagnostics::check_ndjson(reader, &args.manifest, args.root.as_deref()),
+            )?;
+        }
+        let stream =
+            model::ManifestStream::open(&args.manifest).context("failed to load manifest")?;
+        // Only `transaction=op` can run without holding the whole plan.
+        if stream.header.transaction == model::TransactionMode::Op {
+            return apply_stream(args, stream, reporter);
+        }
+        stream.into_plan().context("failed to load manifest")?
+    } else {
+        let text = model::read_manifest(&args.manifest).context("failed to load manifest")?;
+        report_diagnostics(
+            &mut reporter,
+            diagnostics::check(&text, format, &args.manifest, args.root.as_deref()),
+        )?;
+        model::plan_from_text(&text, &args.manifest, format).context("failed to load manifest")?
+    };
+    configure(&mut plan, &args);
     plan.validate()?;
     resolve::validate_root_confinement(&plan)?;
 
@@ -27,7 +50,11 @@ pub fn apply(args: ApplyArgs) -> Result<i32> {
     let normalized = validate::normalize_plan(&plan)?;
 
     // Preflight checks
-    validate::preflight_check(&plan)?;
+    validate::preflight_check(&plan, |index, e| {
+        let id = validate::describe_op(&plan.operations, index);
+        let label = plan.operations[index].meta().label.clone();
+        report_assertion(&mut reporter, &id, &label, e);
+    })?;
 
     if args.validate_only {
         reporter.record(crate::events::Event::PlanValidated {
@@ -36,29 +63,10 @@ pub fn apply(args: ApplyArgs) -> Result<i32> {
         return Ok(exit::SUCCESS);
     }
 
-    // Open journal if needed
-    let journal_writer = if let Some(journal_path) = args.journal {
-        Some(JournalWriter::open(journal_path)?)
-    } else {
-        None
-    };
-
-    let mut txn = TransactionManager::new(
-        plan.transaction,
-        plan.collision_policy,
-        plan.allow_overwrite,
-        journal_writer,
-    );
-
     if args.dry_run {
         // Simulate each operation without writing
-        for op in &normalized {
-            reporter.record(crate::events::Event::OpPlanned {
-                op_id: op.id,
-                op_type: format!("{:?}", op.op),
-                src: op.resolved_src.clone(),
-                dst: op.resolved_dst.clone(),
-            });
+        for (order, op) in normalized.iter().enumerate() {
+            reporter.record(planned_event(order, op));
         }
         reporter.record(crate::events::Event::TxnCommitted {
             plan_id: uuid::Uuid::new_v4(),
@@ -66,32 +74,23 @@ pub fn apply(args: ApplyArgs) -> Result<i32> {
         return Ok(exit::SUCCESS);
     }
 
+    let mut txn = open_transaction(&plan, args.journal)?;
+
     // Real execution
     for op in &normalized {
-        reporter.record(crate::events::Event::OpStarted { op_id: op.id });
-        match txn.execute(op) {
-            Ok(()) => {
-                reporter.record(crate::events::Event::OpCompleted {
-                    op_id: op.id,
-                    bytes_copied: 0, // TODO: fill from result
-                    final_dst: op.resolved_dst.clone().unwrap_or_default(),
-                });
-            }
-            Err(e) => {
-                reporter.record(crate::events::Event::OpFailed {
-                    op_id: op.id,
-                    error: e.to_string(),
-                });
-                if plan.transaction == model::TransactionMode::All {
-                    txn.rollback()?;
-                    reporter.record(crate::events::Event::TxnAborted {
-                        plan_id: uuid::Uuid::new_v4(),
-                    });
-                    return Ok(exit::TRANSACTIONAL_FAILURE);
-                }
-                // In op mode, continue with next operation
+        if let Err(e) = execute_op(&mut txn, &mut reporter, op)
+            && plan.transaction == model::TransactionMode::All
+        {
+            txn.rollback()?;
+            reporter.record(crate::events::Event::TxnAborted {
+                plan_id: uuid::Uuid::new_v4(),
+            });
+            if crate::exit_codes::for_error(&e) == exit::POLICY_FAILURE {
+                return Ok(exit::POLICY_FAILURE);
             }
+            return Ok(exit::TRANSACTIONAL_FAILURE);
         }
+        // In op mode, continue with next operation
     }
 
     txn.commit()?;
@@ -101,6 +100,215 @@ pub fn apply(args: ApplyArgs) -> Result<i32> {
     Ok(exit::SUCCESS)
 }
 
+/// Apply an NDJSON `transaction=op` manifest one operation at a time.
+///
+/// Each operation is validated, preflighted and normalized as it is read and
+/// nothing is kept once it has run, so memory stays bounded (a dry run or
+/// `--validate-only` keeps the paths of simulated operations, so later ones
+/// are preflighted as they would be after them). Implicit path dependencies
+/// are not computed and `after` may only name operations that appear earlier
+/// in the stream. A validation error stops the stream; the
+/// operations already applied stay applied and journaled.
+fn apply_stream(
+    args: ApplyArgs,
+    stream: model::ManifestStream,
+    mut reporter: Reporter,
+) -> Result<i32> {
+    let mut plan = stream.header.clone();
+    configure(&mut plan, &args);
+    plan.validate()?;
+
+    let mut txn = if args.validate_only || args.dry_run {
+        None
+    } else {
+        Some(open_transaction(&plan, args.journal.clone())?)
+    };
+    // Manifest ids seen so far, for duplicate and `after` checks.
+    let mut ids = std::collections::HashSet::new();
+    // Paths of the operations simulated so far by a dry run.
+    let mut touched = validate::Touched::default();
+    let mut order = 0;
+    for (index, op) in stream.enumerate() {
+        let mut op = op.context("failed to load manifest")?;
+        if args.collision_policy.is_some() {
+            op.overrides_mut().collision_policy = None;
+        }
+        op.validate()?;
+        if let Some(after) = op.meta().after.iter().find(|id| !ids.contains(*id)) {
+            anyhow::bail!(
+                "streamed operations may only run after earlier ones: unknown dependency {:?}",
+                after
+            );
+        }
+        let id = match &op.meta().id {
+            Some(id) => {
+                if !ids.insert(id.clone()) {
+                    anyhow::bail!("duplicate operation id: {}", id);
+                }
+                id.clone()
+            }
+            None => format!("#{}", index + 1),
+        };
+        resolve::validate_operation_confinement(&plan.root, &op)?;
+        if let Err(e) = validate::preflight_operation(&plan, &op, &touched) {
+            report_assertion(&mut reporter, &id, &op.meta().label, &e);
+            return Err(e);
+        }
+        let normalized =
+            validate::normalize_operation(&plan.root, &id, &op, &op.meta().after, &touched)?;
+        if txn.is_none() {
+            // Nothing runs, so later operations must not expect this one's
+            // effects on disk.
+            validate::record_touched(&plan.root, &op, &mut touched)?;
+        }
+        for op in normalized {
+            if args.dry_run {
+                reporter.record(planned_event(order, &op));
+            } else if let Some(txn) = &mut txn {
+                // Failures are reported and the stream continues, as in op mode.
+                let _ = execute_op(txn, &mut reporter, &op);
+            }
+            order += 1;
+        }
+    }
+
+    if args.validate_only {
+        reporter.record(crate::events::Event::PlanValidated {
+            plan_id: uuid::Uuid::new_v4(),
+        });
+        return Ok(exit::SUCCESS);
+    }
+    if let Some(txn) = txn {
+        txn.commit()?;
+    }
+    reporter.record(crate::events::Event::TxnCommitted {
+        plan_id: uuid::Uuid::new_v4(),
+    });
+    Ok(exit::SUCCESS)
+}
+
+/// Record every problem found in the manifest as an event and fail with all
+/// of them, rather than with whichever a later step would hit first.
+fn report_diagnostics(
+    reporter: &mut Reporter,
+    diagnostics: Vec<diagnostics::Diagnostic>,
+) -> Result<()> {
+    if diagnostics.is_empty() {
+        return Ok(());
+    }
+    for diagnostic in &diagnostics {
+        reporter.record(crate::events::Event::ManifestDiagnostic {
+            diagnostic: diagnostic.clone(),
+        });
+    }
+    Err(diagnostics::InvalidManifest { diagnostics }.into())
+}
+
+/// Record an `AssertionFailed` event if `e` is a failed assertion, whether
+/// caught by preflight or by execution.
+fn report_assertion(
+    reporter: &mut Reporter,
+    op_id: &str,
+    label: &Option<String>,
+    e: &anyhow::Error,
+) {
+    if let Some(failed) = e.downcast_ref::<crate::assertion::AssertionFailed>() {
+        reporter.record(crate::events::Event::AssertionFailed {
+            op_id: op_id.to_string(),
+            label: label.clone(),
+            path: failed.path.clone(),
+            mismatches: failed.mismatches.clone(),
+        });
+    }
+}
+
+/// Apply command-line overrides to the plan settings.
+fn configure(plan: &mut model::Plan, args: &ApplyArgs) {
+    if let Some(root) = &args.root {
+        plan.root = root.clone();
+    }
+    if let Some(collision_policy) = args.collision_policy {
+        plan.collision_policy = collision_policy;
+        // The command line wins over per-op overrides as well as the plan.
+        for op in &mut plan.operations {
+            op.overrides_mut().collision_policy = None;
+        }
+    }
+    plan.allow_overwrite = args.allow_overwrite;
+}
+
+fn open_transaction(
+    plan: &model::Plan,
+    journal: Option<std::path::PathBuf>,
+) -> Result<TransactionManager> {
+    // Open journal if needed
+    let journal_writer = if let Some(journal_path) = journal {
+        let mut writer = JournalWriter::open(journal_path)?;
+        writer.write_header(&crate::journal::JournalHeader {
+            ts: chrono::Utc::now(),
+            sources: plan.sources.clone(),
+        })?;
+        Some(writer)
+    } else {
+        None
+    };
+
+    Ok(TransactionManager::new(
+        plan.transaction,
+        plan.root.clone(),
+        plan.collision_policy,
+        plan.symlink_policy,
+        plan.allow_cross_device,
+        plan.allow_overwrite,
+        journal_writer,
+    ))
+}
+
+fn planned_event(order: usize, op: &validate::NormalizedOp) -> crate::events::Event {
+    crate::events::Event::OpPlanned {
+        op_id: op.id.clone(),
+        label: op.label.clone(),
+        op_type: format!("{:?}", op.op),
+        src: op.resolved_src.clone(),
+        dst: op.resolved_dst.clone(),
+        order,
+        depends_on: op.depends_on.clone(),
+    }
+}
+
+/// Execute one operation, reporting its events. Returns the error on failure.
+fn execute_op(
+    txn: &mut TransactionManager,
+    reporter: &mut Reporter,
+    op: &validate::NormalizedOp,
+) -> Result<()> {
+    reporter.record(crate::events::Event::OpStarted {
+        op_id: op.id.clone(),
+        label: op.label.clone(),
+    });
+    match txn.execute(op) {
+        Ok(stats) => {
+            reporter.record(crate::events::Event::OpCompleted {
+                op_id: op.id.clone(),
+                label: op.label.clone(),
+                bytes_copied: stats.bytes,
+                members: stats.members,
+                final_dst: op.resolved_dst.clone().unwrap_or_default(),
+            });
+            Ok(())
+        }
+        Err(e) => {
+            report_assertion(reporter, &op.id, &op.label, &e);
+            reporter.record(crate::events::Event::OpFailed {
+                op_id: op.id.clone(),
+                label: op.label.clone(),
+                error: e.to_string(),
+            });
+            Err(e)
+        }
+    }
+}
+
 pub fn undo(args: UndoArgs) -> Result<i32> {
     let mut reporter = Reporter::new(args.json);
     let journal_path = args.journal.clone();
@@ -126,7 +334,22 @@ pub fn undo(args: UndoArgs) -> Result<i32> {
     }
 
     // Real undo
+    let mut settled = std::collections::HashSet::new();
     for entry in entries.iter().rev() {
+        if entry.status != crate::journal::JournalStatus::Start {
+            settled.insert(entry.id.as_str());
+        } else if !settled.contains(entry.id.as_str()) {
+            // An exchange interrupted mid-swap leaves only its pending entry.
+            if let Some(crate::journal::UndoMetadata::Exchange {
+                a,
+                b,
+                fallback_temp: Some(temp),
+                original_inode,
+            }) = &entry.undo
+            {
+                crate::fsops::recover_exchange(a, b, temp, *original_inode)?;
+            }
+        }
         if entry.status != crate::journal::JournalStatus::Ok {
             continue; // skip already undone or failed operations
         }
@@ -173,10 +396,87 @@ pub fn undo(args: UndoArgs) -> Result<i32> {
                     // 2. Restore backup to dst
                     crate::fsops::mv(backup_path, created_dst, false)?;
                 }
+                crate::journal::UndoMetadata::Chmod { previous_modes } => {
+                    crate::fsops::restore_modes(previous_modes)?;
+                }
+                crate::journal::UndoMetadata::Chown {
+                    path,
+                    previous_uid,
+                    previous_gid,
+                } => {
+                    crate::fsops::restore_owner(path, *previous_uid, *previous_gid)?;
+                }
+                crate::journal::UndoMetadata::SetTimes {
+                    path,
+                    previous_mtime,
+                    previous_atime,
+                } => {
+                    crate::fsops::restore_times(path, *previous_mtime, *previous_atime)?;
+                }
+                crate::journal::UndoMetadata::Touch { created_file } => {
+                    std::fs::remove_file(created_file)?;
+                }
+                crate::journal::UndoMetadata::WriteFile {
+                    created_file,
+                    content_hash,
+                    backup_path,
+                } => {
+                    crate::fsops::remove_written_file(created_file, content_hash)?;
+                    if let Some(backup) = backup_path {
+                        crate::fsops::mv(backup, created_file, false)?;
+                    }
+                }
+                crate::journal::UndoMetadata::Edit {
+                    path,
+                    backup_path,
+                    content_hash,
+                } => {
+                    crate::fsops::restore_edited_file(path, backup_path, content_hash)?;
+                }
+                crate::journal::UndoMetadata::Xattr {
+                    path,
+                    name,
+                    previous_value,
+                } => {
+                    crate::fsops::restore_xattr(path, name, previous_value.as_deref())?;
+                }
+                crate::journal::UndoMetadata::Extract { created } => {
+                    crate::fsops::remove_created(created)?;
+                }
+                crate::journal::UndoMetadata::Rmdir {
+                    removed_dir,
+                    snapshot,
+                } => {
+                    crate::fsops::restore_dir(removed_dir, snapshot)?;
+                }
+                crate::journal::UndoMetadata::Exchange { a, b, .. } => {
+                    crate::fsops::exchange(a, b)?;
+                }
+                crate::journal::UndoMetadata::Hardlink {
+                    created_link,
+                    original,
+                    backup_path,
+                } => {
+                    crate::fsops::remove_hardlink(created_link, original)?;
+                    if let Some(backup) = backup_path {
+                        crate::fsops::mv(backup, created_link, false)?;
+                    }
+                }
+                crate::journal::UndoMetadata::Symlink {
+                    created_link,
+                    link_target,
+                    backup_path,
+                } => {
+                    crate::fsops::remove_symlink(created_link, link_target)?;
+                    if let Some(backup) = backup_path {
+                        crate::fsops::mv(backup, created_link, false)?;
+                    }
+                }
             }
             // Write undo journal entry
             let undo_entry = crate::journal::JournalEntry {
-                id: entry.id,
+                id: entry.id.clone(),
+                label: entry.label.clone(),
                 ts: chrono::Utc::now(),
                 op: entry.op.clone(),
                 src: entry.src.clone(),
@@ -5,27 +5,54 @@ use std::path::PathBuf;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Event {
+    /// A problem found while loading or validating the manifest.
+    ManifestDiagnostic {
+        #[serde(flatten)]
+        diagnostic: crate::diagnostics::Diagnostic,
+    },
     PlanValidated {
         plan_id: uuid::Uuid,
     },
     OpPlanned {
-        op_id: uuid::Uuid,
+        op_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
         op_type: String,
         src: Option<PathBuf>,
         dst: Option<PathBuf>,
+        /// Position in the execution order, starting at 0.
+        order: usize,
+        /// Ids of operations that run before this one because it depends on them.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        depends_on: Vec<String>,
     },
     OpStarted {
-        op_id: uuid::Uuid,
+        op_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
     },
     OpCompleted {
-        op_id: uuid::Uuid,
+        op_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
         bytes_copied: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        members: Option<u64>,
         final_dst: PathBuf,
     },
     OpFailed {
-        op_id: uuid::Uuid,
+        op_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
         error: String,
     },
+    AssertionFailed {
+        op_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        path: PathBuf,
+        mismatches: Vec<crate::assertion::Mismatch>,
+    },
     TxnCommitted {
         plan_id: uuid::Uuid,
     },
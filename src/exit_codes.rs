@@ -5,3 +5,12 @@ pub mod exit {
     pub const POLICY_FAILURE: i32 = 2;
     pub const TRANSACTIONAL_FAILURE: i32 = 3;
 }
+
+/// Map an error that aborted a command to its exit code.
+pub fn for_error(err: &anyhow::Error) -> i32 {
+    if err.is::<crate::policy::PolicyViolation>() || err.is::<crate::assertion::AssertionFailed>() {
+        exit::POLICY_FAILURE
+    } else {
+        exit::OPERATIONAL_FAILURE
+    }
+}
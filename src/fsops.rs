@@ -1,4 +1,5 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use std::path::{Path, PathBuf};
 
 /// Result of a filesystem operation.
@@ -20,25 +21,29 @@ pub fn mkdir(dst: &Path, parents: bool) -> Result<()> {
 }
 
 /// Check if two paths are on the same filesystem.
+pub(crate) fn same_filesystem(src: &Path, dst: &Path) -> Result<bool> {
+    let dst_parent = dst.parent().unwrap_or_else(|| Path::new("."));
+    same_device(src, dst_parent)
+}
+
+/// Check if two existing paths live on the same device.
 #[cfg(unix)]
-fn same_filesystem(src: &Path, dst: &Path) -> Result<bool> {
+pub(crate) fn same_device(a: &Path, b: &Path) -> Result<bool> {
     use std::os::unix::fs::MetadataExt;
-    let src_meta = std::fs::metadata(src).context("failed to stat source")?;
-    let dst_parent = dst.parent().unwrap_or_else(|| Path::new("."));
-    let dst_parent_meta =
-        std::fs::metadata(dst_parent).context("failed to stat destination parent")?;
-    Ok(src_meta.dev() == dst_parent_meta.dev())
+    let a_meta = std::fs::metadata(a).context("failed to stat source")?;
+    let b_meta = std::fs::metadata(b).context("failed to stat destination parent")?;
+    Ok(a_meta.dev() == b_meta.dev())
 }
 
 #[cfg(windows)]
-fn same_filesystem(_src: &Path, _dst: &Path) -> Result<bool> {
+pub(crate) fn same_device(_a: &Path, _b: &Path) -> Result<bool> {
     // volume_serial_number is unstable (feature `windows_by_handle`).
     // Fallback to copy+delete which is safe but slower.
     Ok(false)
 }
 
 #[cfg(not(any(unix, windows)))]
-fn same_filesystem(_src: &Path, _dst: &Path) -> Result<bool> {
+pub(crate) fn same_device(_a: &Path, _b: &Path) -> Result<bool> {
     Ok(false)
 }
 
@@ -118,9 +123,1293 @@ pub fn cp(src: &Path, dst: &Path, recursive: bool) -> Result<OpResult> {
     }
 }
 
+/// Change permission bits on `dst` (and everything below it when `recursive`).
+///
+/// Returns the previous mode of every path touched, in the order they were
+/// changed. Directories are changed after their contents, so a mode without
+/// search permission cannot lock the walk out of them. Symlinks found while
+/// recursing are left alone. If any change fails, the paths already changed
+/// are restored before returning the error.
+#[cfg(unix)]
+pub fn chmod(
+    dst: &Path,
+    mode: crate::model::FileMode,
+    recursive: bool,
+) -> Result<Vec<crate::journal::ModeChange>> {
+    let mut targets = Vec::new();
+    if recursive && std::fs::metadata(dst)?.is_dir() {
+        for entry in walkdir::WalkDir::new(dst)
+            .min_depth(1)
+            .contents_first(true)
+            .sort_by_file_name()
+        {
+            let entry = entry?;
+            if !entry.file_type().is_symlink() {
+                targets.push(entry.into_path());
+            }
+        }
+    }
+    targets.push(dst.to_path_buf());
+
+    let mut changed = Vec::new();
+    for path in targets {
+        let previous = match read_mode(&path).and_then(|m| set_mode(&path, mode).map(|_| m)) {
+            Ok(previous) => previous,
+            Err(e) => {
+                restore_modes(&changed)?;
+                return Err(e);
+            }
+        };
+        changed.push(crate::journal::ModeChange {
+            path,
+            previous_mode: previous,
+        });
+    }
+    Ok(changed)
+}
+
+#[cfg(not(unix))]
+pub fn chmod(
+    _dst: &Path,
+    _mode: crate::model::FileMode,
+    _recursive: bool,
+) -> Result<Vec<crate::journal::ModeChange>> {
+    anyhow::bail!("chmod is only supported on Unix");
+}
+
+/// Restore modes recorded by `chmod`, in reverse order.
+pub fn restore_modes(changes: &[crate::journal::ModeChange]) -> Result<()> {
+    for change in changes.iter().rev() {
+        set_mode(&change.path, change.previous_mode)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn read_mode(path: &Path) -> Result<crate::model::FileMode> {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata =
+        std::fs::metadata(path).with_context(|| format!("failed to stat {}", path.display()))?;
+    Ok(crate::model::FileMode(
+        metadata.permissions().mode() & 0o7777,
+    ))
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: crate::model::FileMode) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode.0))
+        .with_context(|| format!("failed to chmod {}", path.display()))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn read_mode(_path: &Path) -> Result<crate::model::FileMode> {
+    anyhow::bail!("file modes are only supported on Unix");
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: crate::model::FileMode) -> Result<()> {
+    anyhow::bail!("chmod is only supported on Unix");
+}
+
+/// Change owner and/or group of `dst`.
+///
+/// Returns the previous `(uid, gid)`.
+#[cfg(unix)]
+pub fn chown(dst: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata =
+        std::fs::metadata(dst).with_context(|| format!("failed to stat {}", dst.display()))?;
+    let previous = (metadata.uid(), metadata.gid());
+    std::os::unix::fs::chown(dst, uid, gid)
+        .with_context(|| format!("failed to chown {}", dst.display()))?;
+    Ok(previous)
+}
+
+#[cfg(not(unix))]
+pub fn chown(_dst: &Path, _uid: Option<u32>, _gid: Option<u32>) -> Result<(u32, u32)> {
+    anyhow::bail!("chown is only supported on Unix");
+}
+
+/// Restore ownership recorded by `chown`.
+#[cfg(unix)]
+pub fn restore_owner(path: &Path, uid: u32, gid: u32) -> Result<()> {
+    std::os::unix::fs::chown(path, Some(uid), Some(gid))
+        .with_context(|| format!("failed to chown {}", path.display()))
+}
+
+#[cfg(not(unix))]
+pub fn restore_owner(_path: &Path, _uid: u32, _gid: u32) -> Result<()> {
+    anyhow::bail!("chown is only supported on Unix");
+}
+
+/// Resolve a user name or numeric uid using the local user database.
+#[cfg(unix)]
+pub fn lookup_user(spec: &str) -> Result<u32> {
+    if let Ok(uid) = spec.parse::<u32>() {
+        return Ok(uid);
+    }
+    let name = std::ffi::CString::new(spec).context("invalid user name")?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        // SAFETY: all pointers refer to live, correctly sized buffers for the
+        // duration of the call, and `passwd` is plain data.
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc == libc::ERANGE {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if rc != 0 {
+            return Err(std::io::Error::from_raw_os_error(rc)).context("user lookup failed");
+        }
+        if result.is_null() {
+            anyhow::bail!("unknown user: {}", spec);
+        }
+        return Ok(pwd.pw_uid);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn lookup_user(spec: &str) -> Result<u32> {
+    spec.parse::<u32>()
+        .map_err(|_| anyhow::anyhow!("unknown user: {}", spec))
+}
+
+/// Resolve a group name or numeric gid using the local group database.
+#[cfg(unix)]
+pub fn lookup_group(spec: &str) -> Result<u32> {
+    if let Ok(gid) = spec.parse::<u32>() {
+        return Ok(gid);
+    }
+    let name = std::ffi::CString::new(spec).context("invalid group name")?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        // SAFETY: see `lookup_user`.
+        let mut grp: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc == libc::ERANGE {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if rc != 0 {
+            return Err(std::io::Error::from_raw_os_error(rc)).context("group lookup failed");
+        }
+        if result.is_null() {
+            anyhow::bail!("unknown group: {}", spec);
+        }
+        return Ok(grp.gr_gid);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn lookup_group(spec: &str) -> Result<u32> {
+    spec.parse::<u32>()
+        .map_err(|_| anyhow::anyhow!("unknown group: {}", spec))
+}
+
+/// Previous state of a path touched by `set_times`.
+pub enum PreviousTimes {
+    /// The file did not exist and was created.
+    Created,
+    /// The file existed with these `(mtime, atime)`.
+    Existing(DateTime<Utc>, DateTime<Utc>),
+}
+
+/// Set modification and/or access time on `dst`, optionally creating it.
+pub fn set_times(
+    dst: &Path,
+    mtime: Option<DateTime<Utc>>,
+    atime: Option<DateTime<Utc>>,
+    create_if_missing: bool,
+) -> Result<PreviousTimes> {
+    let previous = match std::fs::metadata(dst) {
+        Ok(metadata) => PreviousTimes::Existing(
+            from_file_time(filetime::FileTime::from_last_modification_time(&metadata))?,
+            from_file_time(filetime::FileTime::from_last_access_time(&metadata))?,
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && create_if_missing => {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(dst)
+                .with_context(|| format!("failed to create {}", dst.display()))?;
+            PreviousTimes::Created
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to stat {}", dst.display())),
+    };
+
+    let result = match (mtime, atime) {
+        (Some(m), Some(a)) => filetime::set_file_times(dst, to_file_time(a), to_file_time(m)),
+        (Some(m), None) => filetime::set_file_mtime(dst, to_file_time(m)),
+        (None, Some(a)) => filetime::set_file_atime(dst, to_file_time(a)),
+        (None, None) => Ok(()),
+    };
+    if let Err(e) = result {
+        if let PreviousTimes::Created = previous {
+            std::fs::remove_file(dst)?;
+        }
+        return Err(e).with_context(|| format!("failed to set times on {}", dst.display()));
+    }
+    Ok(previous)
+}
+
+/// Restore timestamps recorded by `set_times`.
+pub fn restore_times(path: &Path, mtime: DateTime<Utc>, atime: DateTime<Utc>) -> Result<()> {
+    filetime::set_file_times(path, to_file_time(atime), to_file_time(mtime))
+        .with_context(|| format!("failed to set times on {}", path.display()))
+}
+
+fn to_file_time(ts: DateTime<Utc>) -> filetime::FileTime {
+    filetime::FileTime::from_unix_time(ts.timestamp(), ts.timestamp_subsec_nanos())
+}
+
+fn from_file_time(ft: filetime::FileTime) -> Result<DateTime<Utc>> {
+    DateTime::from_timestamp(ft.unix_seconds(), ft.nanoseconds()).context("timestamp out of range")
+}
+
+/// Create a temp file in `dir` whose mode follows the umask like any newly
+/// created file, rather than the 0600 `NamedTempFile` uses.
+fn new_temp_file(dir: &Path) -> Result<tempfile::NamedTempFile> {
+    let mut builder = tempfile::Builder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(std::fs::Permissions::from_mode(0o666));
+    }
+    builder
+        .tempfile_in(dir)
+        .context("failed to create temp file")
+}
+
+/// Write `content` to a new file at `dst` via a temp file and rename.
+///
+/// Without `mode` the file gets the usual umask-derived permissions. Fails if
+/// `dst` appears while writing. Returns the BLAKE3 hash of the content.
+pub fn write_file(
+    dst: &Path,
+    content: &[u8],
+    mode: Option<crate::model::FileMode>,
+) -> Result<String> {
+    use std::io::Write;
+    let dir = dst.parent().unwrap_or_else(|| Path::new("."));
+    let mut temp = new_temp_file(dir)?;
+    temp.write_all(content)?;
+    if let Some(mode) = mode {
+        set_mode(temp.path(), mode)?;
+    }
+    temp.as_file().sync_all()?;
+    temp.persist_noclobber(dst)
+        .map_err(|e| e.error)
+        .with_context(|| format!("failed to write {}", dst.display()))?;
+    Ok(content_hash(content))
+}
+
+/// Atomically replace `dst` with a copy of `src`.
+///
+/// The copy is written to a temp file in `dst`'s directory, fsynced, given
+/// `mode` (by default the permissions of the `dst` it replaces, or of `src`),
+/// and renamed into place. When `backup` is set, the existing `dst` is first
+/// linked (or copied) to `backup`, so `dst` is never missing.
+pub fn install(
+    src: &Path,
+    dst: &Path,
+    mode: Option<crate::model::FileMode>,
+    backup: Option<&Path>,
+) -> Result<OpResult> {
+    let dir = dst.parent().unwrap_or_else(|| Path::new("."));
+    let mut temp = tempfile::NamedTempFile::new_in(dir).context("failed to create temp file")?;
+    let mut reader = std::fs::File::open(src).context("source not found")?;
+    let bytes = std::io::copy(&mut reader, temp.as_file_mut()).context("copy failed")?;
+    match mode {
+        Some(mode) => set_mode(temp.path(), mode)?,
+        None => {
+            let permissions = match std::fs::metadata(dst) {
+                Ok(metadata) => metadata.permissions(),
+                Err(_) => reader.metadata()?.permissions(),
+            };
+            std::fs::set_permissions(temp.path(), permissions)?;
+        }
+    }
+    temp.as_file().sync_all()?;
+
+    replace_file(temp, dst, backup)?;
+    Ok(OpResult {
+        bytes_copied: bytes,
+        final_dst: dst.to_path_buf(),
+        overwritten: backup.is_some(),
+        backup_path: backup.map(Path::to_path_buf),
+    })
+}
+
+/// Rename a finished temp file over `dst`.
+///
+/// When `backup` is set, the existing `dst` is first linked (or copied) to
+/// `backup`, so `dst` is never missing; otherwise `dst` must not exist.
+fn replace_file(temp: tempfile::NamedTempFile, dst: &Path, backup: Option<&Path>) -> Result<()> {
+    match backup {
+        Some(backup) => {
+            if std::fs::symlink_metadata(backup).is_ok() {
+                anyhow::bail!("backup path already exists: {}", backup.display());
+            }
+            if std::fs::hard_link(dst, backup).is_err() {
+                std::fs::copy(dst, backup).context("failed to create backup")?;
+            }
+            if let Err(e) = temp.persist(dst) {
+                std::fs::remove_file(backup)?;
+                return Err(e.error)
+                    .with_context(|| format!("failed to replace {}", dst.display()));
+            }
+        }
+        None => {
+            temp.persist_noclobber(dst)
+                .map_err(|e| e.error)
+                .with_context(|| format!("failed to create {}", dst.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Compute the result of an `edit` on the file at `path`.
+///
+/// Fails unless exactly `count` matches are found, or at least one when
+/// `count` is unset. All matches are replaced.
+pub fn edited_content(
+    path: &Path,
+    find: &str,
+    replace: &str,
+    regex: bool,
+    count: Option<usize>,
+) -> Result<String> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let text = String::from_utf8(bytes)
+        .map_err(|_| anyhow::anyhow!("cannot edit non-UTF-8 file: {}", path.display()))?;
+    let (matches, edited) = if regex {
+        let pattern =
+            regex::Regex::new(find).map_err(|e| anyhow::anyhow!("invalid edit pattern: {}", e))?;
+        let matches = pattern.find_iter(&text).count();
+        (matches, pattern.replace_all(&text, replace).into_owned())
+    } else {
+        if find.is_empty() {
+            anyhow::bail!("edit requires a non-empty find string");
+        }
+        (text.matches(find).count(), text.replace(find, replace))
+    };
+    match count {
+        Some(expected) if matches != expected => anyhow::bail!(
+            "expected {} matches in {}, found {}",
+            expected,
+            path.display(),
+            matches
+        ),
+        None if matches == 0 => anyhow::bail!("no matches in {}", path.display()),
+        _ => Ok(edited),
+    }
+}
+
+/// Result of `edit`.
+pub struct EditResult {
+    /// The file as it was before the edit, kept for undo.
+    pub backup_path: PathBuf,
+    /// BLAKE3 hash of the edited text.
+    pub content_hash: String,
+    pub bytes: u64,
+}
+
+/// Apply an `edit` to `dst`.
+///
+/// The original file is kept as a backup (see `link_backup`), then the
+/// edited text is written to a temp file with the original mode and renamed
+/// over `dst`.
+pub fn edit(
+    dst: &Path,
+    find: &str,
+    replace: &str,
+    regex: bool,
+    count: Option<usize>,
+) -> Result<EditResult> {
+    let edited = edited_content(dst, find, replace, regex, count)?;
+    let backup_path = link_backup(dst)?;
+    if let Err(e) = replace_text(dst, &edited) {
+        let _ = std::fs::remove_file(&backup_path);
+        return Err(e);
+    }
+    Ok(EditResult {
+        backup_path,
+        content_hash: content_hash(edited.as_bytes()),
+        bytes: edited.len() as u64,
+    })
+}
+
+/// Hard-link `path` to the first free name of `<name>.backup`,
+/// `<name>.1.backup`, … beside it, so each edit of the same file keeps its
+/// own backup without copying it.
+fn link_backup(path: &Path) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .with_context(|| format!("cannot back up {}", path.display()))?;
+    let mut n = 0;
+    loop {
+        let mut backup_name = name.to_os_string();
+        if n > 0 {
+            backup_name.push(format!(".{}", n));
+        }
+        backup_name.push(".backup");
+        let backup = path.with_file_name(backup_name);
+        match std::fs::hard_link(path, &backup) {
+            Ok(()) => return Ok(backup),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to back up {}", path.display()));
+            }
+        }
+    }
+}
+
+/// Put back the backup of a file changed by `edit`, refusing if its content
+/// changed since.
+pub fn restore_edited_file(path: &Path, backup_path: &Path, expected_hash: &str) -> Result<()> {
+    let content =
+        std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    if content_hash(&content) != expected_hash {
+        anyhow::bail!(
+            "refusing to restore {}: content changed since it was edited",
+            path.display()
+        );
+    }
+    std::fs::rename(backup_path, path)
+        .with_context(|| format!("failed to restore {}", path.display()))
+}
+
+/// Atomically replace the contents of the existing file `dst`, keeping its
+/// mode.
+fn replace_text(dst: &Path, text: &str) -> Result<()> {
+    let dir = dst.parent().unwrap_or_else(|| Path::new("."));
+    let mut temp = tempfile::NamedTempFile::new_in(dir).context("failed to create temp file")?;
+    std::io::Write::write_all(temp.as_file_mut(), text.as_bytes())?;
+    let permissions = std::fs::metadata(dst)?.permissions();
+    std::fs::set_permissions(temp.path(), permissions)?;
+    temp.as_file().sync_all()?;
+    temp.persist(dst)
+        .map_err(|e| e.error)
+        .with_context(|| format!("failed to replace {}", dst.display()))?;
+    Ok(())
+}
+
+/// BLAKE3 hash of `content` as lowercase hex.
+pub fn content_hash(content: &[u8]) -> String {
+    blake3::hash(content).to_hex().to_string()
+}
+
+/// Remove a file written by `write_file`, refusing if its content changed.
+pub fn remove_written_file(path: &Path, expected_hash: &str) -> Result<()> {
+    let content =
+        std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    if content_hash(&content) != expected_hash {
+        anyhow::bail!(
+            "refusing to remove {}: content changed since it was written",
+            path.display()
+        );
+    }
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Set (`Some`) or remove (`None`) an extended attribute on `path`.
+///
+/// Returns the previous value, base64-encoded, or `None` if it was absent.
+#[cfg(unix)]
+pub fn set_xattr(path: &Path, name: &str, value: Option<&[u8]>) -> Result<Option<String>> {
+    use base64::Engine;
+    let previous = xattr::get(path, name)
+        .with_context(|| format!("failed to read xattr {} on {}", name, path.display()))?;
+    match value {
+        Some(value) => xattr::set(path, name, value),
+        None => xattr::remove(path, name),
+    }
+    .with_context(|| format!("failed to update xattr {} on {}", name, path.display()))?;
+    Ok(previous.map(|v| base64::engine::general_purpose::STANDARD.encode(v)))
+}
+
+#[cfg(not(unix))]
+pub fn set_xattr(_path: &Path, _name: &str, _value: Option<&[u8]>) -> Result<Option<String>> {
+    anyhow::bail!("extended attributes are only supported on Unix");
+}
+
+/// Restore an extended attribute recorded by `set_xattr`.
+pub fn restore_xattr(path: &Path, name: &str, previous_value: Option<&str>) -> Result<()> {
+    use base64::Engine;
+    let previous = previous_value
+        .map(|v| base64::engine::general_purpose::STANDARD.decode(v))
+        .transpose()
+        .context("invalid xattr value in journal")?;
+    set_xattr(path, name, previous.as_deref())?;
+    Ok(())
+}
+
+/// Swap two existing paths.
+///
+/// Uses `renameat2(RENAME_EXCHANGE)` where available. Otherwise falls back to
+/// a three-step swap through a temp name next to `a`, which is returned so it
+/// can be journaled. A failed fallback reverses the steps already taken.
+pub fn exchange(a: &Path, b: &Path) -> Result<Option<PathBuf>> {
+    exchange_with(a, b, |_| Ok(()))
+}
+
+/// Like `exchange`, but calls `pending` with the temp path before the first
+/// step of a three-step swap, so the caller can journal it. An error from
+/// `pending` aborts the exchange before anything is renamed.
+pub fn exchange_with(
+    a: &Path,
+    b: &Path,
+    pending: impl FnOnce(&Path) -> Result<()>,
+) -> Result<Option<PathBuf>> {
+    if exchange_atomic(a, b)? {
+        return Ok(None);
+    }
+    exchange_three_step(a, b, pending).map(Some)
+}
+
+fn exchange_three_step(
+    a: &Path,
+    b: &Path,
+    pending: impl FnOnce(&Path) -> Result<()>,
+) -> Result<PathBuf> {
+    let temp = exchange_temp_path(a)?;
+    if std::fs::symlink_metadata(&temp).is_ok() {
+        anyhow::bail!("exchange temp path already exists: {}", temp.display());
+    }
+    pending(&temp)?;
+    swap_through(a, b, &temp)?;
+    Ok(temp)
+}
+
+fn swap_through(a: &Path, b: &Path, temp: &Path) -> Result<()> {
+    std::fs::rename(a, temp).context("exchange step 1 failed")?;
+    if let Err(e) = std::fs::rename(b, a) {
+        std::fs::rename(temp, a)?;
+        return Err(e).context("exchange step 2 failed");
+    }
+    if let Err(e) = std::fs::rename(temp, b) {
+        std::fs::rename(a, b)?;
+        std::fs::rename(temp, a)?;
+        return Err(e).context("exchange step 3 failed");
+    }
+    Ok(())
+}
+
+/// Put back a three-step exchange that was interrupted part-way, leaving `a`
+/// and `b` as they were before it started. `original_inode` is the inode `a`
+/// had beforehand; it tells a finished swap from one that never began once
+/// the temp name is gone.
+pub fn recover_exchange(
+    a: &Path,
+    b: &Path,
+    temp: &Path,
+    original_inode: Option<u64>,
+) -> Result<()> {
+    let exists = |p: &Path| std::fs::symlink_metadata(p).is_ok();
+    if exists(temp) {
+        if exists(a) {
+            // Step 2 ran: `b`'s entry now sits at `a`.
+            std::fs::rename(a, b)?;
+        }
+        std::fs::rename(temp, a)?;
+        return Ok(());
+    }
+    match (original_inode, inode(a)) {
+        (Some(before), Some(now)) if before != now => {
+            exchange(a, b)?;
+            Ok(())
+        }
+        (Some(_), Some(_)) => Ok(()),
+        _ => anyhow::bail!(
+            "cannot tell whether the exchange of {} and {} finished",
+            a.display(),
+            b.display()
+        ),
+    }
+}
+
+/// Inode of `path`, without following a final symlink.
+#[cfg(unix)]
+pub fn inode(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::symlink_metadata(path).ok().map(|m| m.ino())
+}
+
+#[cfg(not(unix))]
+pub fn inode(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn exchange_temp_path(a: &Path) -> Result<PathBuf> {
+    let name = a
+        .file_name()
+        .with_context(|| format!("cannot exchange {}", a.display()))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(".tfs-exchange");
+    Ok(a.with_file_name(temp_name))
+}
+
+/// Try an atomic exchange. Returns `false` if the platform or filesystem
+/// does not support it.
+#[cfg(all(target_os = "linux", any(target_env = "gnu", target_env = "musl")))]
+fn exchange_atomic(a: &Path, b: &Path) -> Result<bool> {
+    use std::os::unix::ffi::OsStrExt;
+    let a_c = std::ffi::CString::new(a.as_os_str().as_bytes())?;
+    let b_c = std::ffi::CString::new(b.as_os_str().as_bytes())?;
+    // SAFETY: both pointers are valid NUL-terminated strings for the call.
+    let rc = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a_c.as_ptr(),
+            libc::AT_FDCWD,
+            b_c.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if rc == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EINVAL) | Some(libc::ENOSYS) | Some(libc::ENOTSUP) => Ok(false),
+        _ => Err(err).context("exchange failed"),
+    }
+}
+
+#[cfg(not(all(target_os = "linux", any(target_env = "gnu", target_env = "musl"))))]
+fn exchange_atomic(_a: &Path, _b: &Path) -> Result<bool> {
+    Ok(false)
+}
+
+/// A member of a tar archive, after stripping leading components.
+pub struct ArchiveMember {
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// Result of `extract`.
+pub struct ExtractResult {
+    /// Files and directories created, in creation order.
+    pub created: Vec<PathBuf>,
+    pub bytes: u64,
+    pub members: u64,
+}
+
+fn open_archive(archive: &Path) -> Result<tar::Archive<Box<dyn std::io::Read>>> {
+    use std::io::{BufRead, BufReader};
+    let file = std::fs::File::open(archive)
+        .with_context(|| format!("failed to open archive {}", archive.display()))?;
+    let mut reader = BufReader::new(file);
+    let gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    let reader: Box<dyn std::io::Read> = if gzip {
+        Box::new(flate2::read::GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+/// Validate a member path and strip `strip` leading components.
+///
+/// Absolute paths and `..` components are rejected. Returns `None` for
+/// members that are stripped away entirely.
+fn member_path(path: &Path, strip: usize) -> Result<Option<PathBuf>> {
+    use std::path::Component;
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => components.push(part),
+            Component::CurDir => {}
+            _ => anyhow::bail!("unsafe archive member path: {}", path.display()),
+        }
+    }
+    if components.len() <= strip {
+        return Ok(None);
+    }
+    Ok(Some(components[strip..].iter().collect()))
+}
+
+fn member_is_dir(entry: &tar::Entry<'_, Box<dyn std::io::Read>>) -> Result<Option<bool>> {
+    match entry.header().entry_type() {
+        tar::EntryType::Regular | tar::EntryType::Continuous => Ok(Some(false)),
+        tar::EntryType::Directory => Ok(Some(true)),
+        tar::EntryType::XGlobalHeader => Ok(None),
+        other => anyhow::bail!(
+            "unsupported archive entry type {:?}: {}",
+            other,
+            entry.path()?.display()
+        ),
+    }
+}
+
+/// List the members of a tar archive without extracting anything.
+pub fn archive_members(archive: &Path, strip: usize) -> Result<Vec<ArchiveMember>> {
+    let mut members = Vec::new();
+    for entry in open_archive(archive)?.entries()? {
+        let entry = entry?;
+        let Some(is_dir) = member_is_dir(&entry)? else {
+            continue;
+        };
+        if let Some(path) = member_path(&entry.path()?, strip)? {
+            members.push(ArchiveMember { path, is_dir });
+        }
+    }
+    Ok(members)
+}
+
+/// Extract a tar archive into `dst`, which is created if missing.
+///
+/// Every member is confined to `root` via `resolve::resolve_path` and must not
+/// already exist (directories may). On failure, everything created so far is
+/// removed again.
+pub fn extract(root: &Path, archive: &Path, dst: &Path, strip: usize) -> Result<ExtractResult> {
+    let mut result = ExtractResult {
+        created: Vec::new(),
+        bytes: 0,
+        members: 0,
+    };
+    match extract_into(root, archive, dst, strip, &mut result) {
+        Ok(()) => Ok(result),
+        Err(e) => {
+            remove_created(&result.created)?;
+            Err(e)
+        }
+    }
+}
+
+fn extract_into(
+    root: &Path,
+    archive: &Path,
+    dst: &Path,
+    strip: usize,
+    result: &mut ExtractResult,
+) -> Result<()> {
+    if !dst.exists() {
+        std::fs::create_dir(dst).with_context(|| format!("failed to create {}", dst.display()))?;
+        result.created.push(dst.to_path_buf());
+    }
+    for entry in open_archive(archive)?.entries()? {
+        let mut entry = entry?;
+        let Some(is_dir) = member_is_dir(&entry)? else {
+            continue;
+        };
+        let Some(member) = member_path(&entry.path()?, strip)? else {
+            continue;
+        };
+        let target = crate::resolve::resolve_path(root, &dst.join(&member))?;
+        // Create missing parents for members listed without their directories.
+        if let Some(parent) = target.parent() {
+            let missing: Vec<&Path> = parent.ancestors().take_while(|p| !p.exists()).collect();
+            for dir in missing.into_iter().rev() {
+                std::fs::create_dir(dir)?;
+                result.created.push(dir.to_path_buf());
+            }
+        }
+        if is_dir {
+            if !target.is_dir() {
+                std::fs::create_dir(&target)
+                    .with_context(|| format!("failed to create {}", target.display()))?;
+                result.created.push(target);
+            }
+        } else {
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&target)
+                .with_context(|| format!("failed to create {}", target.display()))?;
+            result.created.push(target.clone());
+            result.bytes += std::io::copy(&mut entry, &mut file)?;
+            drop(file);
+            #[cfg(unix)]
+            set_mode(
+                &target,
+                crate::model::FileMode(entry.header().mode()? & 0o777),
+            )?;
+            if let Ok(mtime) = entry.header().mtime() {
+                let mtime = filetime::FileTime::from_unix_time(mtime as i64, 0);
+                filetime::set_file_mtime(&target, mtime)?;
+            }
+        }
+        result.members += 1;
+    }
+    Ok(())
+}
+
+/// Remove paths created by `extract`, in reverse creation order.
+pub fn remove_created(created: &[PathBuf]) -> Result<()> {
+    for path in created.iter().rev() {
+        if path.is_dir() {
+            std::fs::remove_dir(path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        } else {
+            std::fs::remove_file(path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Result of `archive`.
+pub struct ArchiveResult {
+    pub bytes: u64,
+    pub members: u64,
+}
+
+/// Create a tar archive at `dst` from exactly the paths in `srcs`.
+///
+/// Each source is stored under its path relative to `root`; directories are
+/// walked recursively in name order. Symlinks follow `policy`: `follow`
+/// archives the (confined) target, `skip` leaves the member out and `error`
+/// fails. The archive is written to a temporary file and renamed into place.
+pub fn archive(
+    root: &Path,
+    srcs: &[PathBuf],
+    dst: &Path,
+    compression: crate::model::ArchiveCompression,
+    policy: crate::model::SymlinkPolicy,
+) -> Result<ArchiveResult> {
+    let parent = dst.parent().unwrap_or(Path::new("."));
+    let temp = tempfile::NamedTempFile::new_in(parent)
+        .with_context(|| format!("failed to create temp file in {}", parent.display()))?;
+    let mut result = ArchiveResult {
+        bytes: 0,
+        members: 0,
+    };
+    let file = temp.as_file().try_clone()?;
+    match compression {
+        crate::model::ArchiveCompression::None => {
+            let mut builder = tar::Builder::new(file);
+            append_sources(&mut builder, root, srcs, temp.path(), policy, &mut result)?;
+            builder.into_inner()?;
+        }
+        crate::model::ArchiveCompression::Gzip => {
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            let mut builder = tar::Builder::new(encoder);
+            append_sources(&mut builder, root, srcs, temp.path(), policy, &mut result)?;
+            builder.into_inner()?.finish()?;
+        }
+    }
+    temp.as_file().sync_all()?;
+    temp.persist_noclobber(dst)
+        .map_err(|e| e.error)
+        .with_context(|| format!("failed to create {}", dst.display()))?;
+    Ok(result)
+}
+
+fn append_sources<W: std::io::Write>(
+    builder: &mut tar::Builder<W>,
+    root: &Path,
+    srcs: &[PathBuf],
+    temp: &Path,
+    policy: crate::model::SymlinkPolicy,
+    result: &mut ArchiveResult,
+) -> Result<()> {
+    let root_canon = root.canonicalize()?;
+    for src in srcs {
+        let path = crate::resolve::resolve_entry(root, src)?;
+        let name = path.strip_prefix(&root_canon)?;
+        if name.as_os_str().is_empty() {
+            anyhow::bail!("cannot archive the root itself: {}", src.display());
+        }
+        let walker = walkdir::WalkDir::new(&path)
+            .follow_links(policy == crate::model::SymlinkPolicy::Follow)
+            .sort_by_file_name();
+        for entry in walker {
+            let entry = entry?;
+            if entry.path() == temp {
+                continue;
+            }
+            if entry.path_is_symlink() {
+                match policy {
+                    crate::model::SymlinkPolicy::Follow => {
+                        crate::resolve::resolve_path(root, entry.path())?;
+                    }
+                    crate::model::SymlinkPolicy::Skip => continue,
+                    crate::model::SymlinkPolicy::Error => {
+                        anyhow::bail!("symlink not allowed: {}", entry.path().display())
+                    }
+                }
+            }
+            let relative = entry.path().strip_prefix(&path)?;
+            let member = if relative.as_os_str().is_empty() {
+                name.to_path_buf()
+            } else {
+                name.join(relative)
+            };
+            if entry.file_type().is_dir() {
+                builder.append_dir(&member, entry.path())?;
+            } else if entry.file_type().is_file() {
+                let mut file = std::fs::File::open(entry.path())
+                    .with_context(|| format!("failed to open {}", entry.path().display()))?;
+                result.bytes += file.metadata()?.len();
+                builder.append_file(&member, &mut file)?;
+            } else {
+                anyhow::bail!("unsupported file type: {}", entry.path().display());
+            }
+            result.members += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Remove an empty directory, returning a snapshot of its attributes.
+pub fn rmdir(dst: &Path) -> Result<crate::journal::DirSnapshot> {
+    let metadata =
+        std::fs::metadata(dst).with_context(|| format!("failed to stat {}", dst.display()))?;
+    if !metadata.is_dir() {
+        anyhow::bail!("rmdir target is not a directory: {}", dst.display());
+    }
+    let snapshot = dir_snapshot(&metadata)?;
+    // remove_dir only ever removes empty directories.
+    std::fs::remove_dir(dst).with_context(|| format!("failed to remove {}", dst.display()))?;
+    Ok(snapshot)
+}
+
+#[cfg(unix)]
+fn dir_snapshot(metadata: &std::fs::Metadata) -> Result<crate::journal::DirSnapshot> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    Ok(crate::journal::DirSnapshot {
+        mode: Some(crate::model::FileMode(
+            metadata.permissions().mode() & 0o7777,
+        )),
+        uid: Some(metadata.uid()),
+        gid: Some(metadata.gid()),
+        mtime: from_file_time(filetime::FileTime::from_last_modification_time(metadata))?,
+        atime: from_file_time(filetime::FileTime::from_last_access_time(metadata))?,
+    })
+}
+
+#[cfg(not(unix))]
+fn dir_snapshot(metadata: &std::fs::Metadata) -> Result<crate::journal::DirSnapshot> {
+    Ok(crate::journal::DirSnapshot {
+        mode: None,
+        uid: None,
+        gid: None,
+        mtime: from_file_time(filetime::FileTime::from_last_modification_time(metadata))?,
+        atime: from_file_time(filetime::FileTime::from_last_access_time(metadata))?,
+    })
+}
+
+/// Recreate a directory removed by `rmdir` with its recorded attributes.
+pub fn restore_dir(path: &Path, snapshot: &crate::journal::DirSnapshot) -> Result<()> {
+    std::fs::create_dir(path).with_context(|| format!("failed to recreate {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let current = std::fs::metadata(path)?;
+        if snapshot.uid.is_some_and(|uid| uid != current.uid())
+            || snapshot.gid.is_some_and(|gid| gid != current.gid())
+        {
+            std::os::unix::fs::chown(path, snapshot.uid, snapshot.gid)
+                .with_context(|| format!("failed to chown {}", path.display()))?;
+        }
+    }
+    if let Some(mode) = snapshot.mode {
+        set_mode(path, mode)?;
+    }
+    restore_times(path, snapshot.mtime, snapshot.atime)
+}
+
+/// Create a hard link at `dst` to the regular file `src`.
+pub fn hardlink(src: &Path, dst: &Path) -> Result<OpResult> {
+    let metadata = std::fs::metadata(src).context("source not found")?;
+    if metadata.is_dir() {
+        anyhow::bail!("cannot hardlink a directory: {}", src.display());
+    }
+    std::fs::hard_link(src, dst).context("hardlink failed")?;
+    Ok(OpResult {
+        bytes_copied: 0,
+        final_dst: dst.to_path_buf(),
+        overwritten: false,
+        backup_path: None,
+    })
+}
+
+/// Remove a hard link created by `hardlink`.
+///
+/// On Unix the link is only removed if it still shares an inode with
+/// `original`, so the original file is never touched.
+pub fn remove_hardlink(link: &Path, original: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let link_meta = std::fs::symlink_metadata(link)
+            .with_context(|| format!("hardlink not found: {}", link.display()))?;
+        let original_meta = std::fs::symlink_metadata(original)
+            .with_context(|| format!("hardlink original not found: {}", original.display()))?;
+        if link_meta.dev() != original_meta.dev() || link_meta.ino() != original_meta.ino() {
+            anyhow::bail!(
+                "refusing to remove {}: no longer linked to {}",
+                link.display(),
+                original.display()
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = original;
+    std::fs::remove_file(link)?;
+    Ok(())
+}
+
+/// Create a symbolic link at `dst` pointing to `target`.
+///
+/// When `relative` is set the link stores `target` relative to the link's
+/// directory. Returns the exact contents written to the link.
+pub fn symlink(target: &Path, dst: &Path, relative: bool) -> Result<PathBuf> {
+    let contents = if relative {
+        let link_dir = dst.parent().unwrap_or_else(|| Path::new("."));
+        crate::resolve::relative_to(link_dir, target)
+    } else {
+        target.to_path_buf()
+    };
+    create_symlink(&contents, target, dst).context("failed to create symlink")?;
+    Ok(contents)
+}
+
+#[cfg(unix)]
+fn create_symlink(contents: &Path, _target: &Path, dst: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(contents, dst)
+}
+
+#[cfg(windows)]
+fn create_symlink(contents: &Path, target: &Path, dst: &Path) -> std::io::Result<()> {
+    if target.is_dir() {
+        std::os::windows::fs::symlink_dir(contents, dst)
+    } else {
+        std::os::windows::fs::symlink_file(contents, dst)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_contents: &Path, _target: &Path, _dst: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ))
+}
+
+/// Remove a symlink created by `symlink`, refusing to touch anything else.
+pub fn remove_symlink(link: &Path, expected_contents: &Path) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(link)
+        .with_context(|| format!("symlink not found: {}", link.display()))?;
+    if !metadata.file_type().is_symlink() {
+        anyhow::bail!("refusing to remove non-symlink: {}", link.display());
+    }
+    let contents = std::fs::read_link(link)?;
+    if contents != expected_contents {
+        anyhow::bail!(
+            "refusing to remove symlink with unexpected target: {} -> {}",
+            link.display(),
+            contents.display()
+        );
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileTypeExt;
+        if metadata.file_type().is_symlink_dir() {
+            std::fs::remove_dir(link)?;
+            return Ok(());
+        }
+    }
+    std::fs::remove_file(link)?;
+    Ok(())
+}
+
 /// Trash a file (move to quarantine directory).
 pub fn trash(src: &Path) -> Result<OpResult> {
     // TODO: implement proper trash location
     let dst = src.with_extension("trash");
     mv(src, &dst, false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_member_path() {
+        assert_eq!(
+            member_path(Path::new("pkg/src/lib.rs"), 1).unwrap(),
+            Some(PathBuf::from("src/lib.rs"))
+        );
+        assert_eq!(member_path(Path::new("./pkg/"), 1).unwrap(), None);
+        assert!(member_path(Path::new("../evil"), 0).is_err());
+        assert!(member_path(Path::new("pkg/../../evil"), 0).is_err());
+        assert!(member_path(Path::new("/etc/passwd"), 0).is_err());
+    }
+
+    #[test]
+    fn test_exchange_three_step() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("current");
+        let b = dir.path().join("next");
+        std::fs::create_dir(&a).unwrap();
+        std::fs::write(a.join("v"), "1").unwrap();
+        std::fs::write(&b, "2").unwrap();
+
+        let temp = exchange_three_step(&a, &b, |_| Ok(())).unwrap();
+        assert_eq!(temp, dir.path().join(".current.tfs-exchange"));
+        assert!(!temp.exists());
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "2");
+        assert_eq!(std::fs::read_to_string(b.join("v")).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_exchange_three_step_refuses_existing_temp() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+        std::fs::write(dir.path().join(".a.tfs-exchange"), "stale").unwrap();
+
+        assert!(exchange_three_step(&a, &b, |_| Ok(())).is_err());
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "a");
+        assert_eq!(std::fs::read_to_string(&b).unwrap(), "b");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_recursive_chmod_changes_directories_after_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let site = dir.path().join("site");
+        std::fs::create_dir_all(site.join("css")).unwrap();
+        std::fs::write(site.join("css/app.css"), "").unwrap();
+        std::fs::write(site.join("index.html"), "").unwrap();
+
+        // Without search permission, nothing inside a directory could be
+        // changed after the directory itself.
+        let changes = chmod(&site, crate::model::FileMode(0o644), true).unwrap();
+        let order: Vec<_> = changes
+            .iter()
+            .map(|c| c.path.strip_prefix(&site).unwrap().to_path_buf())
+            .collect();
+        restore_modes(&changes).unwrap();
+        assert_eq!(
+            order,
+            [
+                PathBuf::from("css/app.css"),
+                PathBuf::from("css"),
+                PathBuf::from("index.html"),
+                PathBuf::from(""),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_recover_interrupted_exchange() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let temp = dir.path().join(".a.tfs-exchange");
+        let reset = || {
+            for p in [&a, &b, &temp] {
+                let _ = std::fs::remove_file(p);
+            }
+            std::fs::write(&a, "a").unwrap();
+            std::fs::write(&b, "b").unwrap();
+            inode(&a)
+        };
+        let check = || {
+            assert_eq!(std::fs::read_to_string(&a).unwrap(), "a");
+            assert_eq!(std::fs::read_to_string(&b).unwrap(), "b");
+            assert!(!temp.exists());
+        };
+
+        // Not started.
+        let before = reset();
+        recover_exchange(&a, &b, &temp, before).unwrap();
+        check();
+
+        // After step 1.
+        let before = reset();
+        std::fs::rename(&a, &temp).unwrap();
+        recover_exchange(&a, &b, &temp, before).unwrap();
+        check();
+
+        // After step 2.
+        let before = reset();
+        std::fs::rename(&a, &temp).unwrap();
+        std::fs::rename(&b, &a).unwrap();
+        recover_exchange(&a, &b, &temp, before).unwrap();
+        check();
+
+        // Finished, but never marked ok.
+        let before = reset();
+        swap_through(&a, &b, &temp).unwrap();
+        recover_exchange(&a, &b, &temp, before).unwrap();
+        check();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_file_mode_follows_umask() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        // A plain file created the usual way shows what the umask allows.
+        let reference = dir.path().join("reference");
+        std::fs::write(&reference, "").unwrap();
+
+        let readme = dir.path().join("README.md");
+        write_file(&readme, b"# Docs\n", None).unwrap();
+        assert_eq!(mode(&readme), mode(&reference));
+
+        let secret = dir.path().join("secret");
+        write_file(&secret, b"", Some(crate::model::FileMode(0o600))).unwrap();
+        assert_eq!(mode(&secret), 0o600);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_install_keeps_dst_permissions_and_existing_backup() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("tool.new");
+        let dst = dir.path().join("tool");
+        let backup = dir.path().join("tool.backup");
+        std::fs::write(&src, "v2").unwrap();
+        std::fs::write(&dst, "v1").unwrap();
+        std::fs::set_permissions(&dst, std::fs::Permissions::from_mode(0o750)).unwrap();
+
+        install(&src, &dst, None, Some(&backup)).unwrap();
+        assert_eq!(std::fs::read_to_string(&dst).unwrap(), "v2");
+        assert_eq!(
+            std::fs::metadata(&dst).unwrap().permissions().mode() & 0o777,
+            0o750
+        );
+
+        // An existing backup is never overwritten.
+        std::fs::write(&src, "v3").unwrap();
+        let err = install(&src, &dst, None, Some(&backup)).err().unwrap();
+        assert!(err.to_string().contains("backup path already exists"));
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "v1");
+        assert_eq!(std::fs::read_to_string(&dst).unwrap(), "v2");
+    }
+}
@@ -1,7 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
-use uuid::Uuid;
 
 /// Journal entry status.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -15,8 +14,11 @@ pub enum JournalStatus {
 /// A single journal entry (NDJSON line).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JournalEntry {
-    /// Unique operation ID.
-    pub id: Uuid,
+    /// Operation id (from the manifest, or generated).
+    pub id: String,
+    /// Operation label from the manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
     /// Monotonic timestamp (ISO 8601).
     pub ts: DateTime<Utc>,
     /// Operation type.
@@ -33,6 +35,15 @@ pub struct JournalEntry {
     pub undo: Option<UndoMetadata>,
 }
 
+/// First line written by each run, recording the manifests the plan was loaded from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalHeader {
+    pub ts: DateTime<Utc>,
+    /// The manifest, then every file it included in load order (each with the
+    /// file that included it), with content hashes.
+    pub sources: Vec<crate::model::ManifestSource>,
+}
+
 /// Details about collision resolution.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CollisionDetails {
@@ -66,6 +77,99 @@ pub enum UndoMetadata {
         created_dst: PathBuf,
         backup_path: PathBuf,
     },
+    /// Undo a chmod: restore the previous mode of every touched path.
+    Chmod { previous_modes: Vec<ModeChange> },
+    /// Undo a chown: restore the previous owner and group.
+    Chown {
+        path: PathBuf,
+        previous_uid: u32,
+        previous_gid: u32,
+    },
+    /// Undo a set_times on an existing path: restore the previous timestamps.
+    SetTimes {
+        path: PathBuf,
+        previous_mtime: DateTime<Utc>,
+        previous_atime: DateTime<Utc>,
+    },
+    /// Undo a set_times that created the file: remove it.
+    Touch { created_file: PathBuf },
+    /// Undo a write_file: remove the file if it still has the content `tfs`
+    /// wrote, then restore the backup if one was taken.
+    WriteFile {
+        created_file: PathBuf,
+        content_hash: String,
+        #[serde(default)]
+        backup_path: Option<PathBuf>,
+    },
+    /// Undo an edit: put back the backup of the original file if the file
+    /// still has the content `tfs` wrote.
+    Edit {
+        path: PathBuf,
+        backup_path: PathBuf,
+        content_hash: String,
+    },
+    /// Undo an xattr change: restore the previous value (base64), or remove
+    /// the attribute if it did not exist.
+    Xattr {
+        path: PathBuf,
+        name: String,
+        previous_value: Option<String>,
+    },
+    /// Undo an exchange: exchange the two paths again.
+    Exchange {
+        a: PathBuf,
+        b: PathBuf,
+        /// Temp name used when the atomic exchange was unavailable and a
+        /// three-step swap was performed instead. Journaled in a `start`
+        /// entry before the first step, so `undo` can recover a swap that
+        /// was interrupted.
+        #[serde(default)]
+        fallback_temp: Option<PathBuf>,
+        /// Inode of `a` before the swap, to tell whether an interrupted
+        /// three-step swap had finished.
+        #[serde(default)]
+        original_inode: Option<u64>,
+    },
+    /// Undo an extract: remove every created file and directory, in reverse.
+    Extract { created: Vec<PathBuf> },
+    /// Undo an rmdir: recreate the directory with its recorded attributes.
+    Rmdir {
+        removed_dir: PathBuf,
+        snapshot: DirSnapshot,
+    },
+    /// Undo a hardlink: remove the new link, leaving the original inode
+    /// alone, then restore any destination it replaced.
+    Hardlink {
+        created_link: PathBuf,
+        original: PathBuf,
+        #[serde(default)]
+        backup_path: Option<PathBuf>,
+    },
+    /// Undo a symlink: remove the link, provided it still has the recorded
+    /// target, then restore any destination it replaced.
+    Symlink {
+        created_link: PathBuf,
+        link_target: PathBuf,
+        #[serde(default)]
+        backup_path: Option<PathBuf>,
+    },
+}
+
+/// Previous permission bits of a single path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModeChange {
+    pub path: PathBuf,
+    pub previous_mode: crate::model::FileMode,
+}
+
+/// Attributes of a removed directory, sufficient to recreate it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirSnapshot {
+    pub mode: Option<crate::model::FileMode>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub mtime: DateTime<Utc>,
+    pub atime: DateTime<Utc>,
 }
 
 /// Journal writer that appends NDJSON lines.
@@ -83,6 +187,15 @@ impl JournalWriter {
         Ok(Self { file })
     }
 
+    /// Write the journal header; must come before the run's entries.
+    pub fn write_header(&mut self, header: &JournalHeader) -> anyhow::Result<()> {
+        let line = serde_json::to_string(header)?;
+        use std::io::Write;
+        writeln!(&mut self.file, "{}", line)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+
     /// Write a journal entry.
     pub fn write(&mut self, entry: &JournalEntry) -> anyhow::Result<()> {
         let line = serde_json::to_string(entry)?;
@@ -93,19 +206,33 @@ impl JournalWriter {
     }
 }
 
-/// Read journal entries from a file.
+/// Read journal entries from a file, skipping the header.
 pub fn read_journal(path: PathBuf) -> anyhow::Result<Vec<JournalEntry>> {
     let content = std::fs::read_to_string(path)?;
-    let entries: Vec<JournalEntry> = content
-        .lines()
-        .filter(|line| !line.trim().is_empty())
-        .map(|line| {
-            serde_json::from_str(line).map_err(|e| anyhow::anyhow!("invalid journal line: {}", e))
-        })
-        .collect::<anyhow::Result<_>>()?;
+    let mut entries = Vec::new();
+    for line in content.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            // A header starts each run appended to the journal.
+            Err(_) if serde_json::from_str::<JournalHeader>(line).is_ok() => {}
+            Err(e) => anyhow::bail!("invalid journal line: {}", e),
+        }
+    }
     Ok(entries)
 }
 
+/// Read the header of the first run in the journal, if it has one.
+pub fn read_journal_header(path: PathBuf) -> anyhow::Result<Option<JournalHeader>> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .next()
+        .and_then(|line| serde_json::from_str(line).ok()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -116,10 +243,21 @@ mod tests {
         let journal_path = dir.path().join("journal.jsonl");
 
         let mut writer = JournalWriter::open(journal_path.clone()).unwrap();
+        writer
+            .write_header(&JournalHeader {
+                ts: Utc::now(),
+                sources: vec![crate::model::ManifestSource {
+                    path: PathBuf::from("plan.json"),
+                    included_from: None,
+                    blake3: Some("00".to_string()),
+                }],
+            })
+            .unwrap();
 
-        let id1 = Uuid::new_v4();
+        let id1 = uuid::Uuid::new_v4().to_string();
         let entry1 = JournalEntry {
-            id: id1,
+            id: id1.clone(),
+            label: None,
             ts: Utc::now(),
             op: "op1".to_string(),
             src: None,
@@ -131,9 +269,10 @@ mod tests {
 
         writer.write(&entry1).unwrap();
 
-        let id2 = Uuid::new_v4();
+        let id2 = "move-photos".to_string();
         let entry2 = JournalEntry {
-            id: id2,
+            id: id2.clone(),
+            label: Some("Move photos".to_string()),
             ts: Utc::now(),
             op: "op2".to_string(),
             src: Some(PathBuf::from("src")),
@@ -148,6 +287,8 @@ mod tests {
         writer.write(&entry2).unwrap();
 
         // Read back
+        let header = read_journal_header(journal_path.clone()).unwrap().unwrap();
+        assert_eq!(header.sources[0].path, PathBuf::from("plan.json"));
         let entries = read_journal(journal_path).unwrap();
         assert_eq!(entries.len(), 2);
 
@@ -155,6 +296,7 @@ mod tests {
         assert_eq!(entries[0].status, JournalStatus::Start);
 
         assert_eq!(entries[1].id, id2);
+        assert_eq!(entries[1].label.as_deref(), Some("Move photos"));
         assert_eq!(entries[1].status, JournalStatus::Ok);
         if let Some(UndoMetadata::Move { original_src }) = &entries[1].undo {
             assert_eq!(original_src, &PathBuf::from("orig"));
@@ -1,4 +1,6 @@
+pub mod assertion;
 pub mod cli;
+pub mod diagnostics;
 pub mod engine;
 pub mod events;
 pub mod exit_codes;
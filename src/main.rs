@@ -11,13 +11,31 @@ use tfs::cli::{Cli, Command};
 fn main() -> Result<()> {
     let cli = Cli::parse();
     let exit_code = match cli.command {
-        Command::Schema => {
-            let schema = tfs::model::generate_schema();
-            println!("{}", schema);
-            0
+        Command::Schema(args) => {
+            let schema = match args.version {
+                Some(version) => tfs::model::generate_schema_for(version),
+                None => Ok(tfs::model::generate_schema()),
+            };
+            match schema {
+                Ok(schema) => {
+                    println!("{}", schema);
+                    0
+                }
+                Err(e) => exit_code_for(Err(e)),
+            }
         }
-        Command::Apply(args) => tfs::engine::apply(args)?,
-        Command::Undo(args) => tfs::engine::undo(args)?,
+        Command::Apply(args) => exit_code_for(tfs::engine::apply(args)),
+        Command::Undo(args) => exit_code_for(tfs::engine::undo(args)),
     };
     std::process::exit(exit_code);
 }
+
+fn exit_code_for(result: Result<i32>) -> i32 {
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            tfs::exit_codes::for_error(&e)
+        }
+    }
+}
@@ -1,12 +1,21 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::ValueEnum;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read};
 use std::path::PathBuf;
 
 /// Root execution plan.
+///
+/// Manifests may be written as JSON, YAML or TOML; this schema applies to all three.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Plan {
+    /// Manifest format version. Manifests without one are version 1 and are
+    /// migrated on load.
+    #[serde(default = "legacy_version")]
+    #[schemars(range(min = 1))]
+    pub version: u32,
     /// Absolute root directory; all operations are confined under this root.
     pub root: PathBuf,
     /// Transaction mode.
@@ -21,8 +30,42 @@ pub struct Plan {
     /// Allow overwrite policies (requires explicit opt-in).
     #[serde(default)]
     pub allow_overwrite: bool,
+    /// Allow moves across devices (performed as copy + delete).
+    #[serde(default = "default_true")]
+    pub allow_cross_device: bool,
+    /// Variables substituted as `${name}` into operation paths when loading.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, String>,
+    /// Manifests whose operations run before this one's, relative to this
+    /// manifest. Included manifests may only contain `operations` and further
+    /// `include`s. Resolved (and cleared) when loading.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
     /// List of operations to execute.
     pub operations: Vec<Operation>,
+    /// Files the plan was loaded from, in load order; filled in by `load_plan`.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub sources: Vec<ManifestSource>,
+}
+
+/// A manifest file a plan was loaded from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ManifestSource {
+    pub path: PathBuf,
+    /// The manifest that included this one, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub included_from: Option<PathBuf>,
+    /// BLAKE3 hash of the file contents; not recorded for streamed manifests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+}
+
+/// Current manifest format version.
+pub const CURRENT_VERSION: u32 = 2;
+
+fn legacy_version() -> u32 {
+    1
 }
 
 fn default_transaction_mode() -> TransactionMode {
@@ -37,17 +80,509 @@ fn default_symlink_policy() -> SymlinkPolicy {
     SymlinkPolicy::Error
 }
 
+fn default_true() -> bool {
+    true
+}
+
 impl Plan {
+    /// Symlink policy in effect for `op` (its override, else the plan's).
+    pub fn symlink_policy_for(&self, op: &Operation) -> SymlinkPolicy {
+        op.overrides().symlink_policy.unwrap_or(self.symlink_policy)
+    }
+
+    /// Whether `op` may move across devices (its own setting, else the plan's).
+    pub fn allow_cross_device_for(&self, op: &Operation) -> bool {
+        op.allow_cross_device().unwrap_or(self.allow_cross_device)
+    }
+
+    /// Upgrade a plan loaded as an older manifest version to `CURRENT_VERSION`.
+    fn migrate(&mut self) -> Result<()> {
+        check_version(self.version)?;
+        while self.version < CURRENT_VERSION {
+            match self.version {
+                // Version 2 added `include`; nothing else changed.
+                1 => check_include_version(self.version, &self.include)?,
+                v => anyhow::bail!("no migration from manifest version {}", v),
+            }
+            self.version += 1;
+        }
+        Ok(())
+    }
+
+    /// Splice the operations of every included manifest ahead of this
+    /// plan's own, depth first in `include` order, recording each file in
+    /// `sources`. A file reached again through another include is spliced
+    /// only the first time. `manifest` is the path this plan was read from
+    /// (`-` for stdin, whose includes are relative to the working directory).
+    pub fn resolve_includes(&mut self, manifest: &std::path::Path) -> Result<()> {
+        check_include_version(self.version, &self.include)?;
+        let includes = std::mem::take(&mut self.include);
+        if includes.is_empty() {
+            return Ok(());
+        }
+        let mut stack = Vec::new();
+        if manifest != std::path::Path::new("-") {
+            stack.push(source_path(manifest)?);
+        }
+        let mut operations = splice_includes(&includes, manifest, &mut stack, &mut self.sources)?;
+        operations.append(&mut self.operations);
+        self.operations = operations;
+        Ok(())
+    }
+
+    /// Substitute `${name}` references to `vars` in every operation path.
+    ///
+    /// Variables may refer to other variables. Undefined variables and
+    /// cycles are errors.
+    pub fn expand_vars(&mut self) -> Result<()> {
+        let vars = self.resolved_vars()?;
+        for op in &mut self.operations {
+            op.expand_vars(&vars)?;
+        }
+        Ok(())
+    }
+
+    /// `vars` with every reference to another variable substituted.
+    pub(crate) fn resolved_vars(&self) -> Result<BTreeMap<String, String>> {
+        let mut resolved = BTreeMap::new();
+        for name in self.vars.keys() {
+            resolve_var(&self.vars, name, &mut resolved, &mut Vec::new())?;
+        }
+        Ok(resolved)
+    }
+
     /// Validate the plan (basic sanity checks).
     pub fn validate(&self) -> Result<()> {
         if !self.root.is_absolute() {
             anyhow::bail!("root must be an absolute path");
         }
+        let mut ids = std::collections::HashSet::new();
+        for op in &self.operations {
+            op.validate()?;
+            if let Some(id) = &op.meta().id
+                && !ids.insert(id.as_str())
+            {
+                anyhow::bail!("duplicate operation id: {}", id);
+            }
+        }
         // TODO: more validation
         Ok(())
     }
 }
 
+/// An operation failed `Operation::validate` because of one of its fields.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct InvalidOpField {
+    /// JSON pointer to the field, relative to the operation (e.g. `srcs/1`).
+    pub field: String,
+    pub message: String,
+}
+
+fn invalid_field(field: impl Into<String>, message: String) -> anyhow::Error {
+    InvalidOpField {
+        field: field.into(),
+        message,
+    }
+    .into()
+}
+
+impl Operation {
+    /// Validate a single operation (basic sanity checks).
+    pub fn validate(&self) -> Result<()> {
+        if self.meta().id.as_deref() == Some("") {
+            return Err(invalid_field(
+                "id",
+                "operation id must not be empty".to_string(),
+            ));
+        }
+        if let Operation::Chown {
+            dst,
+            user: None,
+            group: None,
+            ..
+        } = self
+        {
+            anyhow::bail!("chown requires user or group: {}", dst.display());
+        }
+        if let Operation::SetTimes {
+            dst,
+            mtime: None,
+            atime: None,
+            ..
+        } = self
+        {
+            anyhow::bail!("set_times requires mtime or atime: {}", dst.display());
+        }
+        if let Operation::Move {
+            dst,
+            cross_device: true,
+            overrides:
+                OpOverrides {
+                    allow_cross_device: Some(false),
+                    ..
+                },
+            ..
+        } = self
+        {
+            return Err(invalid_field(
+                "allow_cross_device",
+                format!(
+                    "move sets cross_device but allow_cross_device is false: {}",
+                    dst.display()
+                ),
+            ));
+        }
+        if let Operation::Archive { srcs, dst, .. } = self
+            && srcs.is_empty()
+        {
+            return Err(invalid_field(
+                "srcs",
+                format!("archive requires at least one source: {}", dst.display()),
+            ));
+        }
+        if let Operation::MoveInto { srcs, dir, .. } = self {
+            if srcs.is_empty() {
+                return Err(invalid_field(
+                    "srcs",
+                    format!("move_into requires at least one source: {}", dir.display()),
+                ));
+            }
+            if let Some(index) = srcs.iter().position(|src| src.file_name().is_none()) {
+                return Err(invalid_field(
+                    format!("srcs/{}", index),
+                    format!(
+                        "move_into source has no file name: {}",
+                        srcs[index].display()
+                    ),
+                ));
+            }
+        }
+        if let Operation::Assert {
+            path,
+            exists: false,
+            kind,
+            size,
+            sha256,
+            blake3,
+            mode,
+            ..
+        } = self
+            && (kind.is_some()
+                || size.is_some()
+                || sha256.is_some()
+                || blake3.is_some()
+                || mode.is_some())
+        {
+            return Err(invalid_field(
+                "exists",
+                format!(
+                    "assert with exists: false cannot check other properties: {}",
+                    path.display()
+                ),
+            ));
+        }
+        if let Operation::WriteFile { dst, .. } = self {
+            self.write_file_content()
+                .with_context(|| format!("invalid write_file: {}", dst.display()))?;
+        }
+        Ok(())
+    }
+
+    /// The operation's id and label.
+    pub fn meta(&self) -> &OpMeta {
+        match self {
+            Operation::Mkdir { meta, .. }
+            | Operation::Move { meta, .. }
+            | Operation::MoveInto { meta, .. }
+            | Operation::RenameBatch { meta, .. }
+            | Operation::Copy { meta, .. }
+            | Operation::Rename { meta, .. }
+            | Operation::Trash { meta, .. }
+            | Operation::Chmod { meta, .. }
+            | Operation::Chown { meta, .. }
+            | Operation::SetTimes { meta, .. }
+            | Operation::WriteFile { meta, .. }
+            | Operation::SetXattr { meta, .. }
+            | Operation::RemoveXattr { meta, .. }
+            | Operation::Exchange { meta, .. }
+            | Operation::Edit { meta, .. }
+            | Operation::Install { meta, .. }
+            | Operation::Extract { meta, .. }
+            | Operation::Archive { meta, .. }
+            | Operation::Assert { meta, .. }
+            | Operation::Rmdir { meta, .. }
+            | Operation::Hardlink { meta, .. }
+            | Operation::Symlink { meta, .. } => meta,
+        }
+    }
+
+    /// Per-operation policy overrides.
+    pub fn overrides(&self) -> &OpOverrides {
+        match self {
+            Operation::Mkdir { overrides, .. }
+            | Operation::Move { overrides, .. }
+            | Operation::MoveInto { overrides, .. }
+            | Operation::RenameBatch { overrides, .. }
+            | Operation::Copy { overrides, .. }
+            | Operation::Rename { overrides, .. }
+            | Operation::Trash { overrides, .. }
+            | Operation::Chmod { overrides, .. }
+            | Operation::Chown { overrides, .. }
+            | Operation::SetTimes { overrides, .. }
+            | Operation::WriteFile { overrides, .. }
+            | Operation::SetXattr { overrides, .. }
+            | Operation::RemoveXattr { overrides, .. }
+            | Operation::Exchange { overrides, .. }
+            | Operation::Edit { overrides, .. }
+            | Operation::Install { overrides, .. }
+            | Operation::Extract { overrides, .. }
+            | Operation::Archive { overrides, .. }
+            | Operation::Assert { overrides, .. }
+            | Operation::Rmdir { overrides, .. }
+            | Operation::Hardlink { overrides, .. }
+            | Operation::Symlink { overrides, .. } => overrides,
+        }
+    }
+
+    /// Mutable access to the per-operation policy overrides.
+    pub fn overrides_mut(&mut self) -> &mut OpOverrides {
+        match self {
+            Operation::Mkdir { overrides, .. }
+            | Operation::Move { overrides, .. }
+            | Operation::MoveInto { overrides, .. }
+            | Operation::RenameBatch { overrides, .. }
+            | Operation::Copy { overrides, .. }
+            | Operation::Rename { overrides, .. }
+            | Operation::Trash { overrides, .. }
+            | Operation::Chmod { overrides, .. }
+            | Operation::Chown { overrides, .. }
+            | Operation::SetTimes { overrides, .. }
+            | Operation::WriteFile { overrides, .. }
+            | Operation::SetXattr { overrides, .. }
+            | Operation::RemoveXattr { overrides, .. }
+            | Operation::Exchange { overrides, .. }
+            | Operation::Edit { overrides, .. }
+            | Operation::Install { overrides, .. }
+            | Operation::Extract { overrides, .. }
+            | Operation::Archive { overrides, .. }
+            | Operation::Assert { overrides, .. }
+            | Operation::Rmdir { overrides, .. }
+            | Operation::Hardlink { overrides, .. }
+            | Operation::Symlink { overrides, .. } => overrides,
+        }
+    }
+
+    /// Whether the operation itself allows moving across devices: its
+    /// `allow_cross_device` override, else `true` for a move that sets
+    /// `cross_device`.
+    pub fn allow_cross_device(&self) -> Option<bool> {
+        match self {
+            Operation::Move {
+                cross_device: true,
+                overrides,
+                ..
+            } => Some(overrides.allow_cross_device.unwrap_or(true)),
+            _ => self.overrides().allow_cross_device,
+        }
+    }
+
+    /// Paths the operation touches, all of which must stay within the root.
+    ///
+    /// A symlink target is omitted when `allow_external_target` is set.
+    pub fn paths(&self) -> Vec<&PathBuf> {
+        self.named_paths()
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect()
+    }
+
+    /// `paths`, each with the JSON pointer to its field, relative to the
+    /// operation (e.g. `srcs/1`).
+    pub fn named_paths(&self) -> Vec<(String, &PathBuf)> {
+        fn each<'a>(name: &str, paths: &'a [PathBuf]) -> Vec<(String, &'a PathBuf)> {
+            paths
+                .iter()
+                .enumerate()
+                .map(|(i, path)| (format!("{}/{}", name, i), path))
+                .collect()
+        }
+        fn named<'a>(name: &str, path: &'a PathBuf) -> (String, &'a PathBuf) {
+            (name.to_string(), path)
+        }
+        match self {
+            Operation::Mkdir { dst, .. } => vec![named("dst", dst)],
+            Operation::Move { src, dst, .. } => vec![named("src", src), named("dst", dst)],
+            Operation::MoveInto { srcs, dir, .. } => {
+                let mut paths = each("srcs", srcs);
+                paths.push(named("dir", dir));
+                paths
+            }
+            Operation::RenameBatch { srcs, .. } => each("srcs", srcs),
+            Operation::Copy { src, dst, .. } => vec![named("src", src), named("dst", dst)],
+            Operation::Rename { src, dst, .. } => vec![named("src", src), named("dst", dst)],
+            Operation::Trash { src, .. } => vec![named("src", src)],
+            Operation::Chmod { dst, .. } => vec![named("dst", dst)],
+            Operation::Chown { dst, .. } => vec![named("dst", dst)],
+            Operation::SetTimes { dst, .. } => vec![named("dst", dst)],
+            Operation::WriteFile { dst, .. } => vec![named("dst", dst)],
+            Operation::Edit { dst, .. } => vec![named("dst", dst)],
+            Operation::Rmdir { dst, .. } => vec![named("dst", dst)],
+            Operation::SetXattr { dst, .. } | Operation::RemoveXattr { dst, .. } => {
+                vec![named("dst", dst)]
+            }
+            Operation::Hardlink { src, dst, .. } => vec![named("src", src), named("dst", dst)],
+            Operation::Install { src, dst, .. } => vec![named("src", src), named("dst", dst)],
+            Operation::Extract { archive, dst, .. } => {
+                vec![named("archive", archive), named("dst", dst)]
+            }
+            Operation::Exchange { a, b, .. } => vec![named("a", a), named("b", b)],
+            Operation::Archive { srcs, dst, .. } => {
+                let mut paths = each("srcs", srcs);
+                paths.push(named("dst", dst));
+                paths
+            }
+            Operation::Assert { path, .. } => vec![named("path", path)],
+            Operation::Symlink {
+                target,
+                dst,
+                allow_external_target,
+                ..
+            } => {
+                if *allow_external_target {
+                    vec![named("dst", dst)]
+                } else {
+                    vec![named("target", target), named("dst", dst)]
+                }
+            }
+        }
+    }
+
+    /// Substitute `${name}` references to the resolved `vars` in every path.
+    pub(crate) fn expand_vars(&mut self, vars: &BTreeMap<String, String>) -> Result<()> {
+        for path in self.paths_mut() {
+            let Some(text) = path.to_str() else {
+                continue;
+            };
+            if text.contains("${") {
+                *path = PathBuf::from(
+                    substitute(text, &mut |name| {
+                        vars.get(name)
+                            .cloned()
+                            .ok_or_else(|| anyhow::anyhow!("undefined variable: {}", name))
+                    })
+                    .with_context(|| format!("invalid path: {}", text))?,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Mutable access to every path field of the operation.
+    pub fn paths_mut(&mut self) -> Vec<&mut PathBuf> {
+        match self {
+            Operation::Mkdir { dst, .. }
+            | Operation::Trash { src: dst, .. }
+            | Operation::Chmod { dst, .. }
+            | Operation::Chown { dst, .. }
+            | Operation::SetTimes { dst, .. }
+            | Operation::WriteFile { dst, .. }
+            | Operation::Edit { dst, .. }
+            | Operation::Rmdir { dst, .. }
+            | Operation::SetXattr { dst, .. }
+            | Operation::RemoveXattr { dst, .. }
+            | Operation::Assert { path: dst, .. } => vec![dst],
+            Operation::Move { src, dst, .. }
+            | Operation::Copy { src, dst, .. }
+            | Operation::Rename { src, dst, .. }
+            | Operation::Hardlink { src, dst, .. }
+            | Operation::Install { src, dst, .. }
+            | Operation::Extract {
+                archive: src, dst, ..
+            }
+            | Operation::Exchange { a: src, b: dst, .. }
+            | Operation::Symlink {
+                target: src, dst, ..
+            } => vec![src, dst],
+            Operation::MoveInto { srcs, dir, .. } => {
+                srcs.iter_mut().chain(std::iter::once(dir)).collect()
+            }
+            Operation::RenameBatch { srcs, .. } => srcs.iter_mut().collect(),
+            Operation::Archive { srcs, dst, .. } => {
+                srcs.iter_mut().chain(std::iter::once(dst)).collect()
+            }
+        }
+    }
+
+    /// Paths this operation only reads; every other path in `paths()` may be
+    /// created, changed or removed.
+    pub fn read_only_paths(&self) -> Vec<&PathBuf> {
+        match self {
+            Operation::Copy { src, .. }
+            | Operation::Hardlink { src, .. }
+            | Operation::Install { src, .. } => vec![src],
+            Operation::Extract { archive, .. } => vec![archive],
+            Operation::Archive { srcs, .. } => srcs.iter().collect(),
+            Operation::Assert { path, .. } => vec![path],
+            Operation::Symlink { target, .. } => vec![target],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Decoded content of a `write_file` operation.
+    ///
+    /// Exactly one of `content` and `content_base64` must be set.
+    pub fn write_file_content(&self) -> Result<Vec<u8>> {
+        use base64::Engine;
+        match self {
+            Operation::WriteFile {
+                content: Some(text),
+                content_base64: None,
+                ..
+            } => Ok(text.clone().into_bytes()),
+            Operation::WriteFile {
+                content: None,
+                content_base64: Some(encoded),
+                ..
+            } => Ok(base64::engine::general_purpose::STANDARD.decode(encoded)?),
+            Operation::WriteFile { .. } => {
+                anyhow::bail!("exactly one of content or content_base64 is required")
+            }
+            _ => anyhow::bail!("not a write_file operation"),
+        }
+    }
+}
+
+/// Identification and ordering an operation may carry through events and the journal.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct OpMeta {
+    /// Unique id; generated when omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Free-form label for humans and agents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Ids of operations that must run before this one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<String>,
+}
+
+/// Policies an operation may set for itself, taking precedence over the plan.
+///
+/// A collision policy given on the command line still wins over both.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct OpOverrides {
+    /// Collision policy for this operation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collision_policy: Option<CollisionPolicy>,
+    /// Symlink handling policy for this operation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlink_policy: Option<SymlinkPolicy>,
+    /// Whether this operation may move across devices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_cross_device: Option<bool>,
+}
+
 /// Transaction atomicity mode.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
 pub enum TransactionMode {
@@ -59,6 +594,29 @@ pub enum TransactionMode {
     Op,
 }
 
+/// Manifest file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ManifestFormat {
+    Json,
+    Yaml,
+    Toml,
+    /// A header line with the plan settings, then one operation per line.
+    Ndjson,
+}
+
+impl ManifestFormat {
+    /// Format implied by the file extension (`.yaml`/`.yml`, `.toml`,
+    /// `.ndjson`/`.jsonl`), else JSON.
+    pub fn from_path(path: &std::path::Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => ManifestFormat::Yaml,
+            Some("toml") => ManifestFormat::Toml,
+            Some("ndjson" | "jsonl") => ManifestFormat::Ndjson,
+            _ => ManifestFormat::Json,
+        }
+    }
+}
+
 /// Collision resolution policy.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq, ValueEnum)]
 pub enum CollisionPolicy {
@@ -90,6 +648,69 @@ pub enum SymlinkPolicy {
     Error,
 }
 
+/// Type of a filesystem entry, as checked by `assert`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Regular file.
+    #[serde(rename = "file")]
+    File,
+    /// Directory.
+    #[serde(rename = "dir")]
+    Dir,
+    /// Symbolic link.
+    #[serde(rename = "symlink")]
+    Symlink,
+}
+
+/// Compression used by the `archive` operation.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub enum ArchiveCompression {
+    /// Plain tar.
+    #[default]
+    #[serde(rename = "none")]
+    None,
+    /// Gzip-compressed tar.
+    #[serde(rename = "gzip")]
+    Gzip,
+}
+
+/// Unix permission bits, written as an octal string (e.g. `"0644"`).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct FileMode(pub u32);
+
+impl TryFrom<String> for FileMode {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        let digits = value.trim_start_matches("0o");
+        match u32::from_str_radix(digits, 8) {
+            Ok(mode) if !digits.is_empty() && mode <= 0o7777 => Ok(FileMode(mode)),
+            _ => Err(format!("invalid octal mode: {:?}", value)),
+        }
+    }
+}
+
+impl From<FileMode> for String {
+    fn from(mode: FileMode) -> Self {
+        format!("{:04o}", mode.0)
+    }
+}
+
+impl JsonSchema for FileMode {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "FileMode".into()
+    }
+
+    fn json_schema(_generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({
+            "description": "Unix permission bits as an octal string (e.g. \"0644\").",
+            "type": "string",
+            "pattern": "^(0o)?[0-7]{1,4}$"
+        })
+    }
+}
+
 /// A single filesystem operation.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "op", rename_all = "snake_case")]
@@ -101,6 +722,12 @@ pub enum Operation {
         /// Create parent directories as needed.
         #[serde(default)]
         parents: bool,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
     },
     /// Move a file or directory.
     Move {
@@ -111,6 +738,48 @@ pub enum Operation {
         /// Whether to allow cross-device move (copy+delete).
         #[serde(default)]
         cross_device: bool,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Move each of `srcs` into the directory `dir`, keeping file names.
+    ///
+    /// Expanded into one `move` per source during normalization.
+    MoveInto {
+        /// Source paths (relative to root).
+        srcs: Vec<PathBuf>,
+        /// Destination directory (relative to root).
+        dir: PathBuf,
+        /// Create `dir` if it does not exist (its parent must).
+        #[serde(default)]
+        create_dir: bool,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Rename each of `srcs` within its directory using a regex replacement.
+    ///
+    /// The pattern is applied to the file name only. Expanded into one
+    /// `rename` per source during normalization.
+    RenameBatch {
+        /// Source paths (relative to root).
+        srcs: Vec<PathBuf>,
+        /// Regular expression matched against each file name.
+        pattern: String,
+        /// Replacement, with `$1` or `${name}` for capture groups.
+        replacement: String,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
     },
     /// Copy a file or directory.
     Copy {
@@ -121,6 +790,12 @@ pub enum Operation {
         /// Whether to copy recursively for directories.
         #[serde(default)]
         recursive: bool,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
     },
     /// Rename (alias for move within same directory).
     Rename {
@@ -128,11 +803,280 @@ pub enum Operation {
         src: PathBuf,
         /// Destination path (relative to root).
         dst: PathBuf,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
     },
     /// Move to trash/quarantine (optional).
     Trash {
         /// Source path (relative to root).
         src: PathBuf,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Change permission bits of an existing path.
+    Chmod {
+        /// Target path (relative to root).
+        dst: PathBuf,
+        /// New mode as an octal string.
+        mode: FileMode,
+        /// Apply to every entry below a directory as well.
+        #[serde(default)]
+        recursive: bool,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Change owner and/or group of an existing path.
+    Chown {
+        /// Target path (relative to root).
+        dst: PathBuf,
+        /// New owner, as a user name or numeric uid.
+        #[serde(default)]
+        user: Option<String>,
+        /// New group, as a group name or numeric gid.
+        #[serde(default)]
+        group: Option<String>,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Set modification and/or access time of a path (touch).
+    SetTimes {
+        /// Target path (relative to root).
+        dst: PathBuf,
+        /// New modification time (RFC 3339).
+        #[serde(default)]
+        mtime: Option<chrono::DateTime<chrono::Utc>>,
+        /// New access time (RFC 3339).
+        #[serde(default)]
+        atime: Option<chrono::DateTime<chrono::Utc>>,
+        /// Create an empty file if `dst` does not exist.
+        #[serde(default)]
+        create_if_missing: bool,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Create a file with inline content.
+    WriteFile {
+        /// Destination path (relative to root).
+        dst: PathBuf,
+        /// UTF-8 file content (mutually exclusive with `content_base64`).
+        #[serde(default)]
+        content: Option<String>,
+        /// Base64-encoded file content (mutually exclusive with `content`).
+        #[serde(default)]
+        content_base64: Option<String>,
+        /// Mode for the new file as an octal string.
+        #[serde(default)]
+        mode: Option<FileMode>,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Set an extended attribute on an existing path.
+    SetXattr {
+        /// Target path (relative to root).
+        dst: PathBuf,
+        /// Attribute name (e.g. `user.source_url`).
+        name: String,
+        /// Attribute value.
+        value: String,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Remove an extended attribute from an existing path.
+    RemoveXattr {
+        /// Target path (relative to root).
+        dst: PathBuf,
+        /// Attribute name (e.g. `user.source_url`).
+        name: String,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Atomically swap two existing paths.
+    Exchange {
+        /// First path (relative to root).
+        a: PathBuf,
+        /// Second path (relative to root).
+        b: PathBuf,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Replace text in an existing file.
+    Edit {
+        /// File to edit (relative to root).
+        dst: PathBuf,
+        /// Text (or regular expression) to find.
+        find: String,
+        /// Replacement; with `regex`, may use `$1` or `${name}` for capture groups.
+        replace: String,
+        /// Exact number of matches expected; by default at least one is required.
+        #[serde(default)]
+        count: Option<usize>,
+        /// Treat `find` as a regular expression.
+        #[serde(default)]
+        regex: bool,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Atomically replace `dst` with a copy of `src`.
+    Install {
+        /// Source file (relative to root).
+        src: PathBuf,
+        /// Destination path (relative to root).
+        dst: PathBuf,
+        /// Mode for the installed file as an octal string.
+        #[serde(default)]
+        mode: Option<FileMode>,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Extract a tar archive (optionally gzip-compressed) into a directory.
+    Extract {
+        /// Archive path (relative to root).
+        archive: PathBuf,
+        /// Directory to extract into (relative to root); created if missing.
+        dst: PathBuf,
+        /// Number of leading path components to strip from each member.
+        #[serde(default)]
+        strip_components: usize,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Create a tar archive from exactly the listed paths.
+    Archive {
+        /// Paths to archive (relative to root); directories are included recursively.
+        srcs: Vec<PathBuf>,
+        /// Archive path to create (relative to root).
+        dst: PathBuf,
+        /// Compression applied to the archive.
+        #[serde(default)]
+        compression: ArchiveCompression,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Check the current state of a path without changing anything.
+    ///
+    /// Evaluated during preflight and again at its position in the op stream.
+    Assert {
+        /// Path to check (relative to root).
+        path: PathBuf,
+        /// Whether the path must exist (default true).
+        #[serde(default = "default_true")]
+        exists: bool,
+        /// Expected entry type, checked without following symlinks.
+        #[serde(default)]
+        kind: Option<EntryKind>,
+        /// Expected size in bytes.
+        #[serde(default)]
+        size: Option<u64>,
+        /// Expected SHA-256 of the contents, hex-encoded.
+        #[serde(default)]
+        sha256: Option<String>,
+        /// Expected BLAKE3 hash of the contents, hex-encoded.
+        #[serde(default)]
+        blake3: Option<String>,
+        /// Expected permission bits as an octal string.
+        #[serde(default)]
+        mode: Option<FileMode>,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Remove an empty directory.
+    Rmdir {
+        /// Directory path (relative to root).
+        dst: PathBuf,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Create a hard link at `dst` to the existing file `src`.
+    Hardlink {
+        /// Source path (relative to root).
+        src: PathBuf,
+        /// Destination path (relative to root).
+        dst: PathBuf,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
+    },
+    /// Create a symbolic link at `dst` pointing to `target`.
+    Symlink {
+        /// Link target (relative to root, like any other path).
+        target: PathBuf,
+        /// Path of the link to create (relative to root).
+        dst: PathBuf,
+        /// Store the target relative to the link's directory instead of as an absolute path.
+        #[serde(default)]
+        relative: bool,
+        /// Allow the target to point outside the root.
+        #[serde(default)]
+        allow_external_target: bool,
+        /// Optional id and label.
+        #[serde(flatten)]
+        meta: OpMeta,
+        /// Per-operation policy overrides.
+        #[serde(flatten)]
+        overrides: OpOverrides,
     },
 }
 
@@ -142,20 +1086,382 @@ pub fn generate_schema() -> String {
     serde_json::to_string_pretty(&schema).expect("failed to serialize schema")
 }
 
-/// Load a Plan from a JSON file.
+/// JSON Schema for a specific manifest version. Older versions are frozen
+/// copies of the schema as it was released.
+pub fn generate_schema_for(version: u32) -> Result<String> {
+    check_version(version)?;
+    match version {
+        CURRENT_VERSION => Ok(generate_schema()),
+        1 => Ok(include_str!("../schemas/v1.json").trim_end().to_string()),
+        v => anyhow::bail!("no schema for manifest version {}", v),
+    }
+}
+
+/// Reject manifest versions this build does not know.
+pub(crate) fn check_version(version: u32) -> Result<()> {
+    if version == 0 || version > CURRENT_VERSION {
+        anyhow::bail!(
+            "unsupported manifest version {} (this tfs supports versions 1 to {})",
+            version,
+            CURRENT_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// First manifest version that may use `include`.
+const INCLUDE_VERSION: u32 = 2;
+
+/// Reject `include` in a manifest older than the version that added it.
+fn check_include_version(version: u32, include: &[PathBuf]) -> Result<()> {
+    if version < INCLUDE_VERSION && !include.is_empty() {
+        anyhow::bail!(
+            "include requires manifest version {} or later (manifest is version {})",
+            INCLUDE_VERSION,
+            version
+        );
+    }
+    Ok(())
+}
+
+/// Just the `version` of a manifest, read before the rest so that manifests
+/// from newer releases fail with a version error rather than a field error.
+#[derive(Deserialize)]
+struct VersionProbe {
+    #[serde(default = "legacy_version")]
+    version: u32,
+}
+
+/// Resolve `name` to its fully substituted value, memoizing into `resolved`.
+fn resolve_var(
+    vars: &BTreeMap<String, String>,
+    name: &str,
+    resolved: &mut BTreeMap<String, String>,
+    stack: &mut Vec<String>,
+) -> Result<String> {
+    if let Some(value) = resolved.get(name) {
+        return Ok(value.clone());
+    }
+    if let Some(start) = stack.iter().position(|n| n == name) {
+        let mut cycle = stack[start..].to_vec();
+        cycle.push(name.to_string());
+        anyhow::bail!("variable cycle: {}", cycle.join(" -> "));
+    }
+    let Some(raw) = vars.get(name) else {
+        anyhow::bail!("undefined variable: {}", name);
+    };
+    stack.push(name.to_string());
+    let value = substitute(raw, &mut |inner| resolve_var(vars, inner, resolved, stack))?;
+    stack.pop();
+    resolved.insert(name.to_string(), value.clone());
+    Ok(value)
+}
+
+/// Replace every `${name}` in `text` with `lookup(name)`.
+fn substitute(text: &str, lookup: &mut dyn FnMut(&str) -> Result<String>) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start + 2..].find('}') else {
+            anyhow::bail!("unterminated variable reference in {:?}", text);
+        };
+        out.push_str(&lookup(&rest[start + 2..start + 2 + len])?);
+        rest = &rest[start + 2 + len + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Load a Plan from a manifest file in the format implied by its extension.
 pub fn load_plan(path: &std::path::Path) -> Result<Plan> {
-    let file = std::fs::File::open(path)?;
-    let reader = std::io::BufReader::new(file);
-    let plan = serde_json::from_reader(reader)?;
+    load_plan_as(path, ManifestFormat::from_path(path))
+}
+
+/// Load a Plan from a manifest file in the given format, resolving its
+/// includes and expanding its variables. A path of `-` reads standard input.
+pub fn load_plan_as(path: &std::path::Path, format: ManifestFormat) -> Result<Plan> {
+    if format == ManifestFormat::Ndjson {
+        return ManifestStream::open(path)?.into_plan();
+    }
+    plan_from_text(&read_manifest(path)?, path, format)
+}
+
+/// Read a whole manifest; `-` reads standard input.
+pub fn read_manifest(path: &std::path::Path) -> Result<String> {
+    let mut text = String::new();
+    open_manifest(path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Create a Plan from the text of the manifest at `path`, resolving its
+/// includes and expanding its variables.
+pub fn plan_from_text(text: &str, path: &std::path::Path, format: ManifestFormat) -> Result<Plan> {
+    if format == ManifestFormat::Ndjson {
+        return ManifestStream::read(Box::new(std::io::Cursor::new(text.to_string())), path)?
+            .into_plan();
+    }
+    let mut plan = parse_document(text, format)?;
+    let path = source_path(path)?;
+    plan.sources.push(ManifestSource {
+        path: path.clone(),
+        included_from: None,
+        blake3: Some(crate::fsops::content_hash(text.as_bytes())),
+    });
+    plan.resolve_includes(&path)?;
+    plan.expand_vars()?;
     Ok(plan)
 }
 
-/// Create a Plan from a JSON string.
-pub fn from_json(json: &str) -> Result<Plan> {
-    let plan = serde_json::from_str(json)?;
+/// Create a Plan from manifest text, expanding its variables. Includes are
+/// relative to the working directory.
+pub fn parse_plan(text: &str, format: ManifestFormat) -> Result<Plan> {
+    if format == ManifestFormat::Ndjson {
+        return ManifestStream::from_reader(Box::new(std::io::Cursor::new(text.to_string())))?
+            .into_plan();
+    }
+    let mut plan = parse_document(text, format)?;
+    plan.resolve_includes(std::path::Path::new("-"))?;
+    plan.expand_vars()?;
     Ok(plan)
 }
 
+/// Deserialize and migrate a whole manifest, checking its version first.
+fn parse_document(text: &str, format: ManifestFormat) -> Result<Plan> {
+    let probe: VersionProbe = deserialize(text, format)?;
+    check_version(probe.version)?;
+    let mut plan: Plan = deserialize(text, format)?;
+    plan.migrate()?;
+    Ok(plan)
+}
+
+/// A manifest pulled in through `include`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fragment {
+    #[serde(default = "legacy_version")]
+    version: u32,
+    /// Only accepted so that setting it gets a clear error.
+    root: Option<PathBuf>,
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    operations: Vec<Operation>,
+}
+
+/// Load the operations of `includes` (relative to `from`) and everything they
+/// include in turn. `stack` holds the canonical paths of the manifests being
+/// included, to detect cycles; files already in `sources` are skipped.
+fn splice_includes(
+    includes: &[PathBuf],
+    from: &std::path::Path,
+    stack: &mut Vec<PathBuf>,
+    sources: &mut Vec<ManifestSource>,
+) -> Result<Vec<Operation>> {
+    let dir = from.parent().unwrap_or(std::path::Path::new(""));
+    let mut operations = Vec::new();
+    for include in includes {
+        let path = dir
+            .join(include)
+            .canonicalize()
+            .with_context(|| format!("cannot read include {}", include.display()))?;
+        if stack.contains(&path) {
+            let chain: Vec<String> = stack
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|p| p.display().to_string())
+                .collect();
+            anyhow::bail!("include cycle: {}", chain.join(" -> "));
+        }
+        if sources.iter().any(|source| source.path == path) {
+            continue;
+        }
+        let format = ManifestFormat::from_path(&path);
+        if format == ManifestFormat::Ndjson {
+            anyhow::bail!(
+                "included manifests must be JSON, YAML or TOML: {}",
+                path.display()
+            );
+        }
+        let text = std::fs::read_to_string(&path)?;
+        let fragment: Fragment = deserialize(&text, format)
+            .with_context(|| format!("invalid include {}", path.display()))?;
+        check_version(fragment.version)?;
+        check_include_version(fragment.version, &fragment.include)
+            .with_context(|| format!("invalid include {}", path.display()))?;
+        if fragment.root.is_some() {
+            anyhow::bail!("included manifest must not set root: {}", path.display());
+        }
+        sources.push(ManifestSource {
+            path: path.clone(),
+            included_from: Some(from.to_path_buf()),
+            blake3: Some(crate::fsops::content_hash(text.as_bytes())),
+        });
+        stack.push(path.clone());
+        operations.extend(splice_includes(&fragment.include, &path, stack, sources)?);
+        stack.pop();
+        operations.extend(fragment.operations);
+    }
+    Ok(operations)
+}
+
+/// Deserialize a whole JSON, YAML or TOML document.
+fn deserialize<T: serde::de::DeserializeOwned>(text: &str, format: ManifestFormat) -> Result<T> {
+    Ok(match format {
+        ManifestFormat::Json => serde_json::from_str(text)?,
+        ManifestFormat::Yaml => from_yaml(text)?,
+        ManifestFormat::Toml => toml::from_str(text)?,
+        ManifestFormat::Ndjson => anyhow::bail!("NDJSON manifests are read line by line"),
+    })
+}
+
+/// Deserialize a YAML document, with errors on a single line like those of
+/// the other formats.
+pub(crate) fn from_yaml<T: serde::de::DeserializeOwned>(
+    text: &str,
+) -> std::result::Result<T, serde_saphyr::Error> {
+    let options = serde_saphyr::Options {
+        with_snippet: false,
+        ..serde_saphyr::Options::default()
+    };
+    serde_saphyr::from_str_with_options(text, options)
+}
+
+/// Canonical path of a manifest file, or `-` for standard input.
+fn source_path(path: &std::path::Path) -> Result<PathBuf> {
+    if path == std::path::Path::new("-") {
+        Ok(path.to_path_buf())
+    } else {
+        Ok(path.canonicalize()?)
+    }
+}
+
+/// Open a manifest for reading; `-` is standard input.
+fn open_manifest(path: &std::path::Path) -> Result<Box<dyn std::io::BufRead>> {
+    if path == std::path::Path::new("-") {
+        Ok(Box::new(std::io::stdin().lock()))
+    } else {
+        let file = std::fs::File::open(path)?;
+        Ok(Box::new(std::io::BufReader::new(file)))
+    }
+}
+
+/// An NDJSON manifest read one operation at a time.
+///
+/// The first line holds the plan settings (everything but `operations`); each
+/// following non-empty line is one operation, with variables already expanded.
+/// Operations from the header's `include`s come first.
+pub struct ManifestStream {
+    /// Plan settings from the header line; `operations` is empty.
+    pub header: Plan,
+    vars: BTreeMap<String, String>,
+    included: std::vec::IntoIter<Operation>,
+    lines: std::io::Lines<Box<dyn std::io::BufRead>>,
+    line: usize,
+}
+
+impl ManifestStream {
+    /// Open an NDJSON manifest; `-` is standard input.
+    pub fn open(path: &std::path::Path) -> Result<Self> {
+        Self::read(open_manifest(path)?, path)
+    }
+
+    /// Read the header line from `reader`; includes are relative to the
+    /// working directory.
+    pub fn from_reader(reader: Box<dyn std::io::BufRead>) -> Result<Self> {
+        Self::read(reader, std::path::Path::new("-"))
+    }
+
+    fn read(reader: Box<dyn std::io::BufRead>, path: &std::path::Path) -> Result<Self> {
+        let mut lines = reader.lines();
+        let mut line = 0;
+        let header = loop {
+            line += 1;
+            let Some(text) = lines.next().transpose()? else {
+                anyhow::bail!("NDJSON manifest has no header line");
+            };
+            if !text.trim().is_empty() {
+                break text;
+            }
+        };
+        let mut header: serde_json::Value = serde_json::from_str(&header)
+            .with_context(|| format!("invalid NDJSON header on line {}", line))?;
+        let Some(settings) = header.as_object_mut() else {
+            anyhow::bail!("NDJSON header on line {} must be an object", line);
+        };
+        if settings.contains_key("operations") {
+            anyhow::bail!("NDJSON header must not contain operations");
+        }
+        settings.insert(
+            "operations".to_string(),
+            serde_json::Value::Array(Vec::new()),
+        );
+        let probe: VersionProbe = serde_json::from_value(header.clone())
+            .with_context(|| format!("invalid NDJSON header on line {}", line))?;
+        check_version(probe.version)?;
+        let mut header: Plan = serde_json::from_value(header)
+            .with_context(|| format!("invalid NDJSON header on line {}", line))?;
+        header.migrate()?;
+        let path = source_path(path)?;
+        header.sources.push(ManifestSource {
+            path: path.clone(),
+            included_from: None,
+            blake3: None,
+        });
+        header.resolve_includes(&path)?;
+        let included = std::mem::take(&mut header.operations).into_iter();
+        let vars = header.resolved_vars()?;
+        Ok(Self {
+            header,
+            vars,
+            included,
+            lines,
+            line,
+        })
+    }
+
+    /// Read every remaining operation into a complete plan.
+    pub fn into_plan(mut self) -> Result<Plan> {
+        let operations = self.by_ref().collect::<Result<_>>()?;
+        Ok(Plan {
+            operations,
+            ..self.header
+        })
+    }
+}
+
+impl Iterator for ManifestStream {
+    type Item = Result<Operation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(mut op) = self.included.next() {
+            return Some(op.expand_vars(&self.vars).map(|()| op));
+        }
+        loop {
+            self.line += 1;
+            let text = match self.lines.next()? {
+                Ok(text) => text,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if text.trim().is_empty() {
+                continue;
+            }
+            let line = self.line;
+            return Some(
+                serde_json::from_str::<Operation>(&text)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|mut op| op.expand_vars(&self.vars).map(|()| op))
+                    .with_context(|| format!("invalid operation on line {}", line)),
+            );
+        }
+    }
+}
+
+/// Create a Plan from a JSON string, expanding its variables.
+pub fn from_json(json: &str) -> Result<Plan> {
+    parse_plan(json, ManifestFormat::Json)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,6 +1480,11 @@ mod tests {
             symlink_policy: SymlinkPolicy::Error,
             allow_overwrite: false,
             operations: vec![],
+            allow_cross_device: true,
+            vars: BTreeMap::new(),
+            version: CURRENT_VERSION,
+            include: Vec::new(),
+            sources: Vec::new(),
         };
         assert!(plan.validate().is_ok());
     }
@@ -187,7 +1498,183 @@ mod tests {
             symlink_policy: SymlinkPolicy::Error,
             allow_overwrite: false,
             operations: vec![],
+            allow_cross_device: true,
+            vars: BTreeMap::new(),
+            version: CURRENT_VERSION,
+            include: Vec::new(),
+            sources: Vec::new(),
         };
         assert!(plan.validate().is_err());
     }
+
+    #[test]
+    fn test_move_cross_device_settings_must_agree() {
+        let op = |extra: &str| {
+            let json = format!(r#"{{ "op": "move", "src": "a", "dst": "b"{extra} }}"#);
+            serde_json::from_str::<Operation>(&json).unwrap()
+        };
+        let contradiction = op(r#", "cross_device": true, "allow_cross_device": false"#);
+        let err = contradiction.validate().unwrap_err().to_string();
+        assert!(err.contains("allow_cross_device is false"), "{err}");
+        assert_eq!(
+            op(r#", "cross_device": true"#).allow_cross_device(),
+            Some(true)
+        );
+        assert_eq!(op("").allow_cross_device(), None);
+    }
+
+    #[test]
+    fn test_file_mode_parsing() {
+        assert_eq!(FileMode::try_from("0644".to_string()), Ok(FileMode(0o644)));
+        assert_eq!(FileMode::try_from("755".to_string()), Ok(FileMode(0o755)));
+        assert_eq!(
+            FileMode::try_from("0o1777".to_string()),
+            Ok(FileMode(0o1777))
+        );
+        assert!(FileMode::try_from("0999".to_string()).is_err());
+        assert!(FileMode::try_from("17777".to_string()).is_err());
+        assert!(FileMode::try_from("".to_string()).is_err());
+        assert_eq!(String::from(FileMode(0o600)), "0600");
+    }
+
+    #[test]
+    fn test_vars_expand_into_paths() {
+        let plan = from_json(
+            r#"{
+                "root": "/r",
+                "vars": { "base": "Projects/${year}/ClientX", "year": "2026" },
+                "operations": [
+                    { "op": "move", "src": "in/${year}.txt", "dst": "${base}/a.txt" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            plan.operations[0].paths(),
+            [
+                &PathBuf::from("in/2026.txt"),
+                &PathBuf::from("Projects/2026/ClientX/a.txt")
+            ]
+        );
+
+        let undefined =
+            r#"{ "root": "/r", "operations": [ { "op": "mkdir", "dst": "${nope}" } ] }"#;
+        let err = format!("{:#}", from_json(undefined).unwrap_err());
+        assert!(err.contains("undefined variable: nope"), "{err}");
+
+        let cycle = r#"{ "root": "/r", "vars": { "a": "${b}", "b": "x/${a}" }, "operations": [] }"#;
+        let err = from_json(cycle).unwrap_err().to_string();
+        assert!(err.contains("variable cycle: a -> b -> a"), "{err}");
+    }
+
+    #[test]
+    fn test_yaml_and_toml_match_json() {
+        let json = r#"{
+            "root": "/r",
+            "collision_policy": "suffix",
+            "operations": [
+                { "op": "chmod", "dst": "a", "mode": "0644", "id": "perm" },
+                { "op": "move", "src": "a", "dst": "b", "after": ["perm"] }
+            ]
+        }"#;
+        let yaml = r#"
+root: /r
+collision_policy: suffix
+operations:
+  # Comments are the point.
+  - { op: chmod, dst: a, mode: "0644", id: perm }
+  - op: move
+    src: a
+    dst: b
+    after: [perm]
+"#;
+        let toml = r#"
+root = "/r"
+collision_policy = "suffix"
+
+[[operations]]
+op = "chmod"
+dst = "a"
+mode = "0644"
+id = "perm"
+
+[[operations]]
+op = "move"
+src = "a"
+dst = "b"
+after = ["perm"]
+"#;
+        let expected = serde_json::to_value(from_json(json).unwrap()).unwrap();
+        for (text, format) in [(yaml, ManifestFormat::Yaml), (toml, ManifestFormat::Toml)] {
+            let plan = parse_plan(text, format).unwrap();
+            assert_eq!(serde_json::to_value(plan).unwrap(), expected, "{format:?}");
+        }
+        assert_eq!(
+            ManifestFormat::from_path(std::path::Path::new("plan.yml")),
+            ManifestFormat::Yaml
+        );
+    }
+
+    #[test]
+    fn test_ndjson_manifest_stream() {
+        let text = r#"{ "root": "/r", "transaction": "op", "vars": { "d": "docs" } }
+
+{ "op": "mkdir", "dst": "${d}" }
+{ "op": "move", "src": "a", "dst": "${d}/a" }
+{ "op": "bogus" }
+"#;
+        let mut stream =
+            ManifestStream::from_reader(Box::new(std::io::Cursor::new(text.to_string()))).unwrap();
+        assert_eq!(stream.header.transaction, TransactionMode::Op);
+        assert!(stream.header.operations.is_empty());
+        let op = stream.next().unwrap().unwrap();
+        assert_eq!(op.paths(), [&PathBuf::from("docs")]);
+        stream.next().unwrap().unwrap();
+        let err = stream.next().unwrap().unwrap_err().to_string();
+        assert_eq!(err, "invalid operation on line 5");
+        assert!(stream.next().is_none());
+
+        let err = parse_plan(
+            r#"{ "root": "/r", "operations": [] }"#,
+            ManifestFormat::Ndjson,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("must not contain operations"), "{err}");
+    }
+
+    #[test]
+    fn test_manifest_versions() {
+        let legacy = from_json(r#"{ "root": "/r", "operations": [] }"#).unwrap();
+        assert_eq!(legacy.version, CURRENT_VERSION);
+        let v2 = from_json(r#"{ "version": 2, "root": "/r", "operations": [] }"#).unwrap();
+        assert_eq!(v2.version, CURRENT_VERSION);
+        let header = "{\"root\": \"/r\"}\n";
+        assert_eq!(
+            parse_plan(header, ManifestFormat::Ndjson).unwrap().version,
+            CURRENT_VERSION
+        );
+
+        // A newer manifest is rejected by version, not by its unknown shape.
+        let future = r#"{ "version": 99, "root": 7, "operations": {} }"#;
+        let err = from_json(future).unwrap_err().to_string();
+        assert!(err.contains("unsupported manifest version 99"), "{err}");
+        let err = parse_plan(
+            "{\"version\": 99, \"root\": \"/r\"}\n",
+            ManifestFormat::Ndjson,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("unsupported manifest version 99"), "{err}");
+
+        let v1: serde_json::Value = serde_json::from_str(&generate_schema_for(1).unwrap()).unwrap();
+        // The released version 1 schema, before any later field.
+        assert!(v1["properties"].get("version").is_none());
+        assert!(v1["properties"].get("vars").is_none());
+        assert!(v1["properties"].get("allow_cross_device").is_none());
+        let current: serde_json::Value =
+            serde_json::from_str(&generate_schema_for(CURRENT_VERSION).unwrap()).unwrap();
+        assert!(current["properties"].get("version").is_some());
+        assert!(generate_schema_for(CURRENT_VERSION + 1).is_err());
+    }
 }
@@ -1,6 +1,13 @@
 use anyhow::{Result, bail};
 use std::path::Path;
 
+/// A plan was rejected by policy rather than by an I/O failure.
+///
+/// Surfaces as exit code `2` (see `exit_codes`).
+#[derive(Debug, thiserror::Error)]
+#[error("policy violation: {0}")]
+pub struct PolicyViolation(pub String);
+
 /// Check collision policy and compute final destination.
 pub fn resolve_collision(
     policy: crate::model::CollisionPolicy,
@@ -69,6 +76,68 @@ pub fn handle_symlink(policy: crate::model::SymlinkPolicy, path: &Path) -> Resul
     }
 }
 
+/// Ensure the current process may change ownership of `path` as requested.
+#[cfg(unix)]
+pub fn check_chown_privilege(path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(path)?;
+    // SAFETY: these calls have no preconditions.
+    let euid = unsafe { libc::geteuid() };
+    let egid = unsafe { libc::getegid() };
+    let mut groups = vec![egid];
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if count > 0 {
+        let mut supplementary = vec![0 as libc::gid_t; count as usize];
+        let count = unsafe { libc::getgroups(count, supplementary.as_mut_ptr()) };
+        if count > 0 {
+            supplementary.truncate(count as usize);
+            groups.extend(supplementary);
+        }
+    }
+    if let Some(reason) = chown_denial(euid, &groups, metadata.uid(), uid, gid) {
+        return Err(PolicyViolation(format!("cannot chown {}: {}", path.display(), reason)).into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn check_chown_privilege(path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> Result<()> {
+    Err(PolicyViolation(format!(
+        "chown is only supported on Unix: {}",
+        path.display()
+    ))
+    .into())
+}
+
+/// Decide whether a chown is permitted, mirroring the kernel's rules for
+/// processes without `CAP_CHOWN`.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn chown_denial(
+    euid: u32,
+    groups: &[u32],
+    owner: u32,
+    uid: Option<u32>,
+    gid: Option<u32>,
+) -> Option<String> {
+    if euid == 0 {
+        return None;
+    }
+    if let Some(uid) = uid
+        && uid != owner
+    {
+        return Some(format!("changing owner to {} requires root", uid));
+    }
+    if let Some(gid) = gid {
+        if owner != euid {
+            return Some(format!("file is owned by uid {}, not {}", owner, euid));
+        }
+        if !groups.contains(&gid) {
+            return Some(format!("process is not a member of group {}", gid));
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -119,6 +188,18 @@ mod tests {
         assert_eq!(backup, Some(dir.path().join("file.txt.backup")));
     }
 
+    #[test]
+    fn test_chown_denial() {
+        // root may do anything
+        assert!(chown_denial(0, &[0], 1000, Some(0), Some(0)).is_none());
+        // unprivileged: no owner change, group change only to own groups on own files
+        assert!(chown_denial(1000, &[1000], 1000, Some(0), None).is_some());
+        assert!(chown_denial(1000, &[1000], 1000, Some(1000), None).is_none());
+        assert!(chown_denial(1000, &[1000, 27], 1000, None, Some(27)).is_none());
+        assert!(chown_denial(1000, &[1000], 1000, None, Some(27)).is_some());
+        assert!(chown_denial(1000, &[1000, 27], 1001, None, Some(27)).is_some());
+    }
+
     #[test]
     fn test_handle_symlink() {
         let dir = tempdir().unwrap();
@@ -1,15 +1,18 @@
 use crate::events::Event;
 
 /// Reporter aggregates events and produces human or JSON output.
+///
+/// Events are emitted as they are recorded and not retained, so streamed
+/// plans do not grow memory with the number of operations.
 pub struct Reporter {
-    events: Vec<Event>,
+    recorded: usize,
     json_mode: bool,
 }
 
 impl Reporter {
     pub fn new(json_mode: bool) -> Self {
         Self {
-            events: Vec::new(),
+            recorded: 0,
             json_mode,
         }
     }
@@ -21,11 +24,11 @@ impl Reporter {
                 println!("{}", line);
             }
         }
-        self.events.push(event);
+        self.recorded += 1;
     }
 
     pub fn summary(&self) -> String {
         // TODO: produce human-readable summary
-        format!("{} events recorded", self.events.len())
+        format!("{} events recorded", self.recorded)
     }
 }
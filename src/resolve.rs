@@ -91,19 +91,71 @@ pub fn resolve_path(root: &Path, path: &Path) -> Result<PathBuf> {
     Ok(normalized)
 }
 
+/// Resolve a path like `resolve_path`, but without following a symlink in the
+/// final component, so the result names the entry itself.
+pub fn resolve_entry(root: &Path, path: &Path) -> Result<PathBuf> {
+    match path.file_name() {
+        Some(name) => {
+            let parent = path.parent().unwrap_or(Path::new(""));
+            Ok(resolve_path(root, parent)?.join(name))
+        }
+        None => resolve_path(root, path),
+    }
+}
+
+/// Resolve a symlink target.
+///
+/// The result is only normalized lexically against the root, so symlinks on
+/// the way to the target stay in the link and retargeting them later still
+/// takes effect. Targets are confined like any other path, following every
+/// symlink, unless `allow_external` is set, in which case they may point
+/// anywhere.
+pub fn resolve_link_target(root: &Path, target: &Path, allow_external: bool) -> Result<PathBuf> {
+    if !allow_external {
+        resolve_path(root, target)?;
+    }
+    let root_canon = root
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("root error {}: {}", root.display(), e))?;
+    Ok(normalize_lexical(&root_canon.join(target)))
+}
+
+/// Compute `path` relative to the directory `base` (both absolute, normalized).
+pub fn relative_to(base: &Path, path: &Path) -> PathBuf {
+    let base_components: Vec<Component> = base.components().collect();
+    let path_components: Vec<Component> = path.components().collect();
+    let common = base_components
+        .iter()
+        .zip(path_components.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base_components.len() {
+        relative.push("..");
+    }
+    for component in &path_components[common..] {
+        relative.push(component.as_os_str());
+    }
+    if relative.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        relative
+    }
+}
+
 /// Validate that all operations stay within root.
 pub fn validate_root_confinement(plan: &crate::model::Plan) -> Result<()> {
     for op in &plan.operations {
-        let paths = match op {
-            crate::model::Operation::Mkdir { dst, .. } => vec![dst],
-            crate::model::Operation::Move { src, dst, .. } => vec![src, dst],
-            crate::model::Operation::Copy { src, dst, .. } => vec![src, dst],
-            crate::model::Operation::Rename { src, dst } => vec![src, dst],
-            crate::model::Operation::Trash { src } => vec![src],
-        };
-        for path in paths {
-            resolve_path(&plan.root, path)?;
-        }
+        validate_operation_confinement(&plan.root, op)?;
+    }
+    Ok(())
+}
+
+/// Check that every path of `op` stays under `root`.
+pub fn validate_operation_confinement(root: &Path, op: &crate::model::Operation) -> Result<()> {
+    for path in op.paths() {
+        resolve_path(root, path)?;
     }
     Ok(())
 }
@@ -136,6 +188,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_relative_to() {
+        assert_eq!(
+            relative_to(Path::new("/r/a/b"), Path::new("/r/a/c/f")),
+            PathBuf::from("../c/f")
+        );
+        assert_eq!(
+            relative_to(Path::new("/r"), Path::new("/r/x/y")),
+            PathBuf::from("x/y")
+        );
+        assert_eq!(
+            relative_to(Path::new("/r/a"), Path::new("/r/a")),
+            PathBuf::from(".")
+        );
+    }
+
     #[test]
     fn resolve_rejects_parent_escape() {
         let dir = tempfile::tempdir().unwrap();
@@ -1,25 +1,43 @@
 use anyhow::{Context, Result};
 use chrono::Utc;
 
+/// Byte and member counts reported for a completed operation.
+#[derive(Debug, Default)]
+pub struct OpStats {
+    pub bytes: u64,
+    /// Number of archive members, for `extract` and `archive`.
+    pub members: Option<u64>,
+}
+
 /// Transaction manager for `all` or `op` mode.
 pub struct TransactionManager {
-    _mode: crate::model::TransactionMode,
+    mode: crate::model::TransactionMode,
+    root: std::path::PathBuf,
     collision_policy: crate::model::CollisionPolicy,
+    symlink_policy: crate::model::SymlinkPolicy,
+    allow_cross_device: bool,
     allow_overwrite: bool,
     journal_writer: Option<crate::journal::JournalWriter>,
+    /// Entries to undo on rollback; only kept in `all` mode.
     applied: Vec<crate::journal::JournalEntry>,
 }
 
 impl TransactionManager {
     pub fn new(
         mode: crate::model::TransactionMode,
+        root: std::path::PathBuf,
         collision_policy: crate::model::CollisionPolicy,
+        symlink_policy: crate::model::SymlinkPolicy,
+        allow_cross_device: bool,
         allow_overwrite: bool,
         journal_writer: Option<crate::journal::JournalWriter>,
     ) -> Self {
         Self {
-            _mode: mode,
+            mode,
+            root,
             collision_policy,
+            symlink_policy,
+            allow_cross_device,
             allow_overwrite,
             journal_writer,
             applied: Vec::new(),
@@ -27,10 +45,11 @@ impl TransactionManager {
     }
 
     /// Execute a single operation within the transaction.
-    pub fn execute(&mut self, op: &crate::validate::NormalizedOp) -> Result<()> {
+    pub fn execute(&mut self, op: &crate::validate::NormalizedOp) -> Result<OpStats> {
         // Write journal entry "start"
         let entry = crate::journal::JournalEntry {
-            id: op.id,
+            id: op.id.clone(),
+            label: op.label.clone(),
             ts: Utc::now(),
             op: format!("{:?}", op.op),
             src: op.resolved_src.clone(),
@@ -53,24 +72,52 @@ impl TransactionManager {
         let mut backup_path_opt = None;
         let mut collision_details = None;
 
-        if let Some(dst) = dst_opt {
+        // Per-op overrides take precedence over the plan defaults.
+        let overrides = op.op.overrides();
+        let collision_policy = self.collision_policy_for(&op.op);
+        let symlink_policy = overrides.symlink_policy.unwrap_or(self.symlink_policy);
+        let allow_cross_device = op
+            .op
+            .allow_cross_device()
+            .unwrap_or(self.allow_cross_device);
+
+        if let Some(dst) = dst_opt
+            && creates_dst(&op.op)
+        {
             // resolve_collision returns (final_dst, backup_path)
             let (resolved, backup) =
-                crate::policy::resolve_collision(self.collision_policy, dst, self.allow_overwrite)?;
+                crate::policy::resolve_collision(collision_policy, dst, self.allow_overwrite)?;
 
-            if resolved != dst || backup.is_some() {
-                collision_details = Some(crate::journal::CollisionDetails {
-                    policy: self.collision_policy,
-                    final_dst: resolved.clone(),
-                    backup_path: backup.clone(),
-                });
-            }
+            // Record the effective policy even when nothing collided.
+            collision_details = Some(crate::journal::CollisionDetails {
+                policy: collision_policy,
+                final_dst: resolved.clone(),
+                backup_path: backup.clone(),
+            });
             final_dst_path = resolved;
             backup_path_opt = backup;
         }
 
-        // Perform backup if needed
-        if let Some(backup) = &backup_path_opt {
+        if let (Some(src), Some(dst)) = (src, dst_opt)
+            && !allow_cross_device
+            && matches!(
+                op.op,
+                crate::model::Operation::Move { .. } | crate::model::Operation::Rename { .. }
+            )
+            && !crate::fsops::same_filesystem(src, dst)?
+        {
+            return Err(crate::policy::PolicyViolation(format!(
+                "cross-device move not allowed: {} -> {}",
+                src.display(),
+                dst.display()
+            ))
+            .into());
+        }
+
+        // Perform backup if needed (install keeps dst in place and backs up itself)
+        if let Some(backup) = &backup_path_opt
+            && !matches!(op.op, crate::model::Operation::Install { .. })
+        {
             // We need to move the EXISTING dst to backup
             // dst_opt must be Some here
             let dst = dst_opt.unwrap();
@@ -78,10 +125,12 @@ impl TransactionManager {
         }
 
         // Execute based on operation type
+        let mut stats = OpStats::default();
         match &op.op {
             crate::model::Operation::Mkdir {
                 dst: dst_path,
                 parents,
+                ..
             } => {
                 let dst = if op.resolved_dst.is_some() {
                     &final_dst_path
@@ -93,12 +142,13 @@ impl TransactionManager {
                 let undo = crate::journal::UndoMetadata::Mkdir {
                     created_dir: dst.to_path_buf(),
                 };
-                self.record_success(op.id, src, Some(dst), collision_details, Some(undo))?;
+                self.record_success(op, src, Some(dst), collision_details, Some(undo))?;
             }
             crate::model::Operation::Move {
                 src: src_path,
                 dst: dst_path,
                 cross_device,
+                ..
             } => {
                 let src = src.unwrap_or(src_path.as_path());
                 let dst = if op.resolved_dst.is_some() {
@@ -106,7 +156,7 @@ impl TransactionManager {
                 } else {
                     dst_path.as_path()
                 };
-                let _result = crate::fsops::mv(src, dst, *cross_device)?;
+                stats.bytes = crate::fsops::mv(src, dst, *cross_device)?.bytes_copied;
 
                 let undo = if let Some(bk) = backup_path_opt {
                     crate::journal::UndoMetadata::MoveWithOverwrite {
@@ -118,12 +168,13 @@ impl TransactionManager {
                         original_src: src.to_path_buf(),
                     }
                 };
-                self.record_success(op.id, Some(src), Some(dst), collision_details, Some(undo))?;
+                self.record_success(op, Some(src), Some(dst), collision_details, Some(undo))?;
             }
             crate::model::Operation::Copy {
                 src: src_path,
                 dst: dst_path,
                 recursive,
+                ..
             } => {
                 let src = src.unwrap_or(src_path.as_path());
                 let dst = if op.resolved_dst.is_some() {
@@ -131,7 +182,7 @@ impl TransactionManager {
                 } else {
                     dst_path.as_path()
                 };
-                let _result = crate::fsops::cp(src, dst, *recursive)?;
+                stats.bytes = crate::fsops::cp(src, dst, *recursive)?.bytes_copied;
 
                 let undo = if let Some(bk) = backup_path_opt {
                     crate::journal::UndoMetadata::CopyWithOverwrite {
@@ -143,11 +194,12 @@ impl TransactionManager {
                         created_dst: dst.to_path_buf(),
                     }
                 };
-                self.record_success(op.id, Some(src), Some(dst), collision_details, Some(undo))?;
+                self.record_success(op, Some(src), Some(dst), collision_details, Some(undo))?;
             }
             crate::model::Operation::Rename {
                 src: src_path,
                 dst: dst_path,
+                ..
             } => {
                 let src = src.unwrap_or(src_path.as_path());
                 let dst = if op.resolved_dst.is_some() {
@@ -155,7 +207,7 @@ impl TransactionManager {
                 } else {
                     dst_path.as_path()
                 };
-                let _result = crate::fsops::mv(src, dst, false)?;
+                stats.bytes = crate::fsops::mv(src, dst, false)?.bytes_copied;
 
                 let undo = if let Some(bk) = backup_path_opt {
                     crate::journal::UndoMetadata::MoveWithOverwrite {
@@ -167,30 +219,329 @@ impl TransactionManager {
                         original_src: src.to_path_buf(),
                     }
                 };
-                self.record_success(op.id, Some(src), Some(dst), collision_details, Some(undo))?;
+                self.record_success(op, Some(src), Some(dst), collision_details, Some(undo))?;
             }
-            crate::model::Operation::Trash { src: src_path } => {
+            crate::model::Operation::Trash { src: src_path, .. } => {
                 let src = src.unwrap_or(src_path.as_path());
                 let result = crate::fsops::trash(src)?;
                 let undo = crate::journal::UndoMetadata::Move {
                     original_src: src.to_path_buf(),
                 };
-                self.record_success(op.id, Some(src), Some(&result.final_dst), None, Some(undo))?;
+                self.record_success(op, Some(src), Some(&result.final_dst), None, Some(undo))?;
+            }
+            crate::model::Operation::Chmod {
+                dst: dst_path,
+                mode,
+                recursive,
+                ..
+            } => {
+                let dst = dst_opt.unwrap_or(dst_path.as_path());
+                let previous_modes = crate::fsops::chmod(dst, *mode, *recursive)?;
+                let undo = crate::journal::UndoMetadata::Chmod { previous_modes };
+                self.record_success(op, None, Some(dst), None, Some(undo))?;
+            }
+            crate::model::Operation::Chown {
+                dst: dst_path,
+                user,
+                group,
+                ..
+            } => {
+                let dst = dst_opt.unwrap_or(dst_path.as_path());
+                let uid = user.as_deref().map(crate::fsops::lookup_user).transpose()?;
+                let gid = group
+                    .as_deref()
+                    .map(crate::fsops::lookup_group)
+                    .transpose()?;
+                let (previous_uid, previous_gid) = crate::fsops::chown(dst, uid, gid)?;
+                let undo = crate::journal::UndoMetadata::Chown {
+                    path: dst.to_path_buf(),
+                    previous_uid,
+                    previous_gid,
+                };
+                self.record_success(op, None, Some(dst), None, Some(undo))?;
+            }
+            crate::model::Operation::SetTimes {
+                dst: dst_path,
+                mtime,
+                atime,
+                create_if_missing,
+                ..
+            } => {
+                let dst = dst_opt.unwrap_or(dst_path.as_path());
+                let undo = match crate::fsops::set_times(dst, *mtime, *atime, *create_if_missing)? {
+                    crate::fsops::PreviousTimes::Created => crate::journal::UndoMetadata::Touch {
+                        created_file: dst.to_path_buf(),
+                    },
+                    crate::fsops::PreviousTimes::Existing(previous_mtime, previous_atime) => {
+                        crate::journal::UndoMetadata::SetTimes {
+                            path: dst.to_path_buf(),
+                            previous_mtime,
+                            previous_atime,
+                        }
+                    }
+                };
+                self.record_success(op, None, Some(dst), None, Some(undo))?;
+            }
+            crate::model::Operation::WriteFile {
+                dst: dst_path,
+                mode,
+                ..
+            } => {
+                let dst = if op.resolved_dst.is_some() {
+                    &final_dst_path
+                } else {
+                    dst_path.as_path()
+                };
+                let content = op.op.write_file_content()?;
+                let content_hash = crate::fsops::write_file(dst, &content, *mode)?;
+                stats.bytes = content.len() as u64;
+                let undo = crate::journal::UndoMetadata::WriteFile {
+                    created_file: dst.to_path_buf(),
+                    content_hash,
+                    backup_path: backup_path_opt,
+                };
+                self.record_success(op, None, Some(dst), collision_details, Some(undo))?;
+            }
+            crate::model::Operation::SetXattr {
+                dst: dst_path,
+                name,
+                value,
+                ..
+            } => {
+                let dst = dst_opt.unwrap_or(dst_path.as_path());
+                let previous_value = crate::fsops::set_xattr(dst, name, Some(value.as_bytes()))?;
+                let undo = crate::journal::UndoMetadata::Xattr {
+                    path: dst.to_path_buf(),
+                    name: name.clone(),
+                    previous_value,
+                };
+                self.record_success(op, None, Some(dst), None, Some(undo))?;
+            }
+            crate::model::Operation::RemoveXattr {
+                dst: dst_path,
+                name,
+                ..
+            } => {
+                let dst = dst_opt.unwrap_or(dst_path.as_path());
+                let previous_value = crate::fsops::set_xattr(dst, name, None)?;
+                let undo = crate::journal::UndoMetadata::Xattr {
+                    path: dst.to_path_buf(),
+                    name: name.clone(),
+                    previous_value,
+                };
+                self.record_success(op, None, Some(dst), None, Some(undo))?;
+            }
+            crate::model::Operation::Edit {
+                dst: dst_path,
+                find,
+                replace,
+                count,
+                regex,
+                ..
+            } => {
+                let dst = dst_opt.unwrap_or(dst_path.as_path());
+                let result = crate::fsops::edit(dst, find, replace, *regex, *count)?;
+                stats.bytes = result.bytes;
+                let undo = crate::journal::UndoMetadata::Edit {
+                    path: dst.to_path_buf(),
+                    backup_path: result.backup_path,
+                    content_hash: result.content_hash,
+                };
+                self.record_success(op, None, Some(dst), None, Some(undo))?;
+            }
+            crate::model::Operation::Install {
+                src: src_path,
+                dst: dst_path,
+                mode,
+                ..
+            } => {
+                let src = src.unwrap_or(src_path.as_path());
+                let dst = if op.resolved_dst.is_some() {
+                    &final_dst_path
+                } else {
+                    dst_path.as_path()
+                };
+                stats.bytes = crate::fsops::install(src, dst, *mode, backup_path_opt.as_deref())?
+                    .bytes_copied;
+
+                let undo = if let Some(bk) = backup_path_opt {
+                    crate::journal::UndoMetadata::Overwrite { backup_path: bk }
+                } else {
+                    crate::journal::UndoMetadata::Copy {
+                        created_dst: dst.to_path_buf(),
+                    }
+                };
+                self.record_success(op, Some(src), Some(dst), collision_details, Some(undo))?;
+            }
+            crate::model::Operation::Extract {
+                archive,
+                dst: dst_path,
+                strip_components,
+                ..
+            } => {
+                let archive = src.unwrap_or(archive.as_path());
+                let dst = dst_opt.unwrap_or(dst_path.as_path());
+                let result = crate::fsops::extract(&self.root, archive, dst, *strip_components)?;
+                stats.bytes = result.bytes;
+                stats.members = Some(result.members);
+                let undo = crate::journal::UndoMetadata::Extract {
+                    created: result.created,
+                };
+                self.record_success(op, Some(archive), Some(dst), None, Some(undo))?;
+            }
+            crate::model::Operation::Archive {
+                srcs,
+                dst: dst_path,
+                compression,
+                ..
+            } => {
+                let dst = if op.resolved_dst.is_some() {
+                    &final_dst_path
+                } else {
+                    dst_path.as_path()
+                };
+                let result =
+                    crate::fsops::archive(&self.root, srcs, dst, *compression, symlink_policy)?;
+                stats.bytes = result.bytes;
+                stats.members = Some(result.members);
+
+                let undo = if let Some(bk) = backup_path_opt {
+                    crate::journal::UndoMetadata::CopyWithOverwrite {
+                        created_dst: dst.to_path_buf(),
+                        backup_path: bk,
+                    }
+                } else {
+                    crate::journal::UndoMetadata::Copy {
+                        created_dst: dst.to_path_buf(),
+                    }
+                };
+                self.record_success(op, None, Some(dst), collision_details, Some(undo))?;
+            }
+            crate::model::Operation::MoveInto { .. }
+            | crate::model::Operation::RenameBatch { .. } => {
+                anyhow::bail!("composite operations must be expanded by normalize_plan");
+            }
+            crate::model::Operation::Assert { path, .. } => {
+                let path = dst_opt.unwrap_or(path.as_path());
+                crate::assertion::check(&self.root, &op.op)?;
+                self.record_success(op, None, Some(path), None, None)?;
+            }
+            crate::model::Operation::Rmdir { dst: dst_path, .. } => {
+                let dst = dst_opt.unwrap_or(dst_path.as_path());
+                let snapshot = crate::fsops::rmdir(dst)?;
+                let undo = crate::journal::UndoMetadata::Rmdir {
+                    removed_dir: dst.to_path_buf(),
+                    snapshot,
+                };
+                self.record_success(op, None, Some(dst), None, Some(undo))?;
+            }
+            crate::model::Operation::Exchange {
+                a: a_path,
+                b: b_path,
+                ..
+            } => {
+                let a = src.unwrap_or(a_path.as_path());
+                let b = dst_opt.unwrap_or(b_path.as_path());
+                let original_inode = crate::fsops::inode(a);
+                let fallback_temp = crate::fsops::exchange_with(a, b, |temp| {
+                    // Journal the temp name before the first rename, so a
+                    // crash mid-swap can be recovered by `undo`.
+                    let pending = crate::journal::JournalEntry {
+                        id: op.id.clone(),
+                        label: op.label.clone(),
+                        ts: Utc::now(),
+                        op: format!("{:?}", op.op),
+                        src: Some(a.to_path_buf()),
+                        dst: Some(b.to_path_buf()),
+                        collision: None,
+                        status: crate::journal::JournalStatus::Start,
+                        undo: Some(crate::journal::UndoMetadata::Exchange {
+                            a: a.to_path_buf(),
+                            b: b.to_path_buf(),
+                            fallback_temp: Some(temp.to_path_buf()),
+                            original_inode,
+                        }),
+                    };
+                    self.write_journal(&pending)
+                })?;
+                let undo = crate::journal::UndoMetadata::Exchange {
+                    a: a.to_path_buf(),
+                    b: b.to_path_buf(),
+                    fallback_temp,
+                    original_inode,
+                };
+                self.record_success(op, Some(a), Some(b), None, Some(undo))?;
+            }
+            crate::model::Operation::Hardlink {
+                src: src_path,
+                dst: dst_path,
+                ..
+            } => {
+                let src = src.unwrap_or(src_path.as_path());
+                let dst = if op.resolved_dst.is_some() {
+                    &final_dst_path
+                } else {
+                    dst_path.as_path()
+                };
+                crate::fsops::hardlink(src, dst)?;
+                let undo = crate::journal::UndoMetadata::Hardlink {
+                    created_link: dst.to_path_buf(),
+                    original: src.to_path_buf(),
+                    backup_path: backup_path_opt,
+                };
+                self.record_success(op, Some(src), Some(dst), collision_details, Some(undo))?;
+            }
+            crate::model::Operation::Symlink {
+                target: target_path,
+                dst: dst_path,
+                relative,
+                ..
+            } => {
+                let target = src.unwrap_or(target_path.as_path());
+                let dst = if op.resolved_dst.is_some() {
+                    &final_dst_path
+                } else {
+                    dst_path.as_path()
+                };
+                let link_target = crate::fsops::symlink(target, dst, *relative)?;
+                let undo = crate::journal::UndoMetadata::Symlink {
+                    created_link: dst.to_path_buf(),
+                    link_target,
+                    backup_path: backup_path_opt,
+                };
+                self.record_success(op, Some(target), Some(dst), collision_details, Some(undo))?;
             }
         }
-        Ok(())
+        Ok(stats)
+    }
+
+    /// Collision policy in effect for `op` (its override, else the plan's).
+    fn collision_policy_for(&self, op: &crate::model::Operation) -> crate::model::CollisionPolicy {
+        op.overrides()
+            .collision_policy
+            .unwrap_or(self.collision_policy)
     }
 
+    /// Journal a finished operation. Operations that did not resolve a
+    /// collision still record the collision policy in effect for them.
     fn record_success(
         &mut self,
-        id: uuid::Uuid,
+        op: &crate::validate::NormalizedOp,
         src: Option<&std::path::Path>,
         dst: Option<&std::path::Path>,
         collision: Option<crate::journal::CollisionDetails>,
         undo: Option<crate::journal::UndoMetadata>,
     ) -> Result<()> {
+        let collision = collision.or_else(|| {
+            dst.map(|dst| crate::journal::CollisionDetails {
+                policy: self.collision_policy_for(&op.op),
+                final_dst: dst.to_path_buf(),
+                backup_path: None,
+            })
+        });
         let entry = crate::journal::JournalEntry {
-            id,
+            id: op.id.clone(),
+            label: op.label.clone(),
             ts: Utc::now(),
             op: "".to_string(),
             src: src.map(|p| p.to_path_buf()),
@@ -200,7 +551,9 @@ impl TransactionManager {
             undo,
         };
         self.write_journal(&entry)?;
-        self.applied.push(entry);
+        if self.mode == crate::model::TransactionMode::All {
+            self.applied.push(entry);
+        }
         Ok(())
     }
 
@@ -262,10 +615,87 @@ impl TransactionManager {
                         // Note: we used created_dst as the path, which should equal entry.dst
                         crate::fsops::mv(backup_path, created_dst, false)?;
                     }
+                    crate::journal::UndoMetadata::Chmod { previous_modes } => {
+                        crate::fsops::restore_modes(previous_modes)?;
+                    }
+                    crate::journal::UndoMetadata::Chown {
+                        path,
+                        previous_uid,
+                        previous_gid,
+                    } => {
+                        crate::fsops::restore_owner(path, *previous_uid, *previous_gid)?;
+                    }
+                    crate::journal::UndoMetadata::SetTimes {
+                        path,
+                        previous_mtime,
+                        previous_atime,
+                    } => {
+                        crate::fsops::restore_times(path, *previous_mtime, *previous_atime)?;
+                    }
+                    crate::journal::UndoMetadata::Touch { created_file } => {
+                        std::fs::remove_file(created_file)?;
+                    }
+                    crate::journal::UndoMetadata::WriteFile {
+                        created_file,
+                        content_hash,
+                        backup_path,
+                    } => {
+                        crate::fsops::remove_written_file(created_file, content_hash)?;
+                        if let Some(backup) = backup_path {
+                            crate::fsops::mv(backup, created_file, false)?;
+                        }
+                    }
+                    crate::journal::UndoMetadata::Edit {
+                        path,
+                        backup_path,
+                        content_hash,
+                    } => {
+                        crate::fsops::restore_edited_file(path, backup_path, content_hash)?;
+                    }
+                    crate::journal::UndoMetadata::Xattr {
+                        path,
+                        name,
+                        previous_value,
+                    } => {
+                        crate::fsops::restore_xattr(path, name, previous_value.as_deref())?;
+                    }
+                    crate::journal::UndoMetadata::Extract { created } => {
+                        crate::fsops::remove_created(created)?;
+                    }
+                    crate::journal::UndoMetadata::Rmdir {
+                        removed_dir,
+                        snapshot,
+                    } => {
+                        crate::fsops::restore_dir(removed_dir, snapshot)?;
+                    }
+                    crate::journal::UndoMetadata::Exchange { a, b, .. } => {
+                        crate::fsops::exchange(a, b)?;
+                    }
+                    crate::journal::UndoMetadata::Hardlink {
+                        created_link,
+                        original,
+                        backup_path,
+                    } => {
+                        crate::fsops::remove_hardlink(created_link, original)?;
+                        if let Some(backup) = backup_path {
+                            crate::fsops::mv(backup, created_link, false)?;
+                        }
+                    }
+                    crate::journal::UndoMetadata::Symlink {
+                        created_link,
+                        link_target,
+                        backup_path,
+                    } => {
+                        crate::fsops::remove_symlink(created_link, link_target)?;
+                        if let Some(backup) = backup_path {
+                            crate::fsops::mv(backup, created_link, false)?;
+                        }
+                    }
                 }
                 // Write undo journal entry
                 let undo_entry = crate::journal::JournalEntry {
-                    id: entry.id,
+                    id: entry.id.clone(),
+                    label: entry.label.clone(),
                     ts: Utc::now(),
                     op: entry.op.clone(),
                     src: entry.src.clone(),
@@ -280,3 +710,21 @@ impl TransactionManager {
         Ok(())
     }
 }
+
+/// Whether an operation creates its destination and is therefore subject to
+/// the collision policy. Operations that modify an existing path are not.
+fn creates_dst(op: &crate::model::Operation) -> bool {
+    !matches!(
+        op,
+        crate::model::Operation::Chmod { .. }
+            | crate::model::Operation::Chown { .. }
+            | crate::model::Operation::SetTimes { .. }
+            | crate::model::Operation::SetXattr { .. }
+            | crate::model::Operation::RemoveXattr { .. }
+            | crate::model::Operation::Exchange { .. }
+            | crate::model::Operation::Rmdir { .. }
+            | crate::model::Operation::Extract { .. }
+            | crate::model::Operation::Assert { .. }
+            | crate::model::Operation::Edit { .. }
+    )
+}
@@ -3,7 +3,11 @@ use std::path::{Path, PathBuf};
 
 /// Normalized operation ready for execution.
 pub struct NormalizedOp {
-    pub id: uuid::Uuid,
+    /// The manifest id, or `#<n>` (its manifest position) when it has none.
+    pub id: String,
+    pub label: Option<String>,
+    /// Ids of the normalized operations this one must run after.
+    pub depends_on: Vec<String>,
     pub op: crate::model::Operation,
     pub resolved_src: Option<PathBuf>,
     pub resolved_dst: Option<PathBuf>,
@@ -13,21 +17,257 @@ pub struct NormalizedOp {
 /// Validate and normalize a plan into a deterministic operation stream.
 pub fn normalize_plan(plan: &crate::model::Plan) -> Result<Vec<NormalizedOp>> {
     let mut normalized = Vec::new();
-    for op in &plan.operations {
-        let (resolved_src, resolved_dst) = resolve_operation_paths(&plan.root, op)?;
-        let parents = compute_parent_dirs(&resolved_dst, op);
+    let mut ids = std::collections::HashSet::new();
+    // Normalized ids produced by each manifest operation, by manifest index.
+    let mut produced: Vec<Vec<String>> = vec![Vec::new(); plan.operations.len()];
+    let mut touched = Touched::default();
+    for (index, dependencies) in execution_order(plan)? {
+        let op = &plan.operations[index];
+        // Operations without an id are named by their manifest position, so
+        // the ids (and those of anything they expand to) are the same every run.
+        let id = describe_op(&plan.operations, index);
+        let depends_on: Vec<String> = dependencies
+            .iter()
+            .flat_map(|&d| produced[d].iter().cloned())
+            .collect();
+        for normalized_op in normalize_operation(&plan.root, &id, op, &depends_on, &touched)? {
+            if !ids.insert(normalized_op.id.clone()) {
+                anyhow::bail!("duplicate operation id: {}", normalized_op.id);
+            }
+            produced[index].push(normalized_op.id.clone());
+            normalized.push(normalized_op);
+        }
+        record_touched(&plan.root, op, &mut touched)?;
+    }
+    Ok(normalized)
+}
+
+/// Normalize a single manifest operation with the given id, expanding
+/// composite operations. `touched` holds the paths of earlier operations
+/// that have not run yet.
+pub fn normalize_operation(
+    root: &Path,
+    id: &str,
+    op: &crate::model::Operation,
+    depends_on: &[String],
+    touched: &Touched,
+) -> Result<Vec<NormalizedOp>> {
+    let mut normalized = Vec::new();
+    for (id, op) in expand_operation(root, id, op, touched)? {
+        let (resolved_src, resolved_dst) = resolve_operation_paths(root, &op)?;
+        let parents = compute_parent_dirs(&resolved_dst, &op);
         normalized.push(NormalizedOp {
-            id: uuid::Uuid::new_v4(),
-            op: op.clone(),
+            id,
+            label: op.meta().label.clone(),
+            depends_on: depends_on.to_vec(),
+            op,
             resolved_src,
             resolved_dst,
             parents,
         });
     }
-    // Ensure deterministic ordering (already same as input)
     Ok(normalized)
 }
 
+/// Order the plan's operations by their dependencies.
+///
+/// Edges come from `after` and, implicitly, from any earlier operation whose
+/// paths overlap this one's where either side writes (e.g. a move of an
+/// earlier op's dst, or a copy into a directory created by an earlier mkdir).
+/// Among ready operations the earliest in the manifest always runs first, so
+/// only `after` can move an operation ahead of manifest order. Returns
+/// `(index, dependencies)` pairs in execution order.
+pub fn execution_order(plan: &crate::model::Plan) -> Result<Vec<(usize, Vec<usize>)>> {
+    use std::cmp::Reverse;
+    use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
+
+    let ops = &plan.operations;
+    let by_id: HashMap<&str, usize> = ops
+        .iter()
+        .enumerate()
+        .filter_map(|(i, op)| op.meta().id.as_deref().map(|id| (id, i)))
+        .collect();
+
+    // Earlier operations by the paths they touch, and by the paths they
+    // write, so overlaps are found without comparing every pair.
+    let mut touched_by: BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+    let mut written_by: BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+    let mut dependencies = vec![BTreeSet::new(); ops.len()];
+    for (i, op) in ops.iter().enumerate() {
+        for after in &op.meta().after {
+            let Some(&j) = by_id.get(after.as_str()) else {
+                anyhow::bail!("unknown dependency {:?} of {}", after, describe_op(ops, i));
+            };
+            dependencies[i].insert(j);
+        }
+        let read_only = op.read_only_paths();
+        let mut paths = Vec::new();
+        for p in op.paths() {
+            let written = !read_only.contains(&p);
+            let resolved = crate::resolve::resolve_path(&plan.root, p)?;
+            // A write conflicts with any earlier access, a read only with
+            // earlier writes.
+            let earlier = if written { &touched_by } else { &written_by };
+            dependencies[i].extend(overlapping(earlier, &resolved).flatten());
+            paths.push((resolved, written));
+        }
+        for (resolved, written) in paths {
+            if written {
+                written_by.entry(resolved.clone()).or_default().push(i);
+            }
+            touched_by.entry(resolved).or_default().push(i);
+        }
+    }
+
+    let mut remaining: Vec<usize> = dependencies.iter().map(BTreeSet::len).collect();
+    let mut dependents = vec![Vec::new(); ops.len()];
+    for (i, deps) in dependencies.iter().enumerate() {
+        for &j in deps {
+            dependents[j].push(i);
+        }
+    }
+    let mut ready: BinaryHeap<Reverse<usize>> = (0..ops.len())
+        .filter(|&i| remaining[i] == 0)
+        .map(Reverse)
+        .collect();
+    let mut order = Vec::with_capacity(ops.len());
+    while let Some(Reverse(i)) = ready.pop() {
+        order.push((i, dependencies[i].iter().copied().collect()));
+        for &k in &dependents[i] {
+            remaining[k] -= 1;
+            if remaining[k] == 0 {
+                ready.push(Reverse(k));
+            }
+        }
+    }
+    if order.len() < ops.len() {
+        let cycle: Vec<String> = (0..ops.len())
+            .filter(|&i| remaining[i] > 0)
+            .map(|i| describe_op(ops, i))
+            .collect();
+        anyhow::bail!("dependency cycle among operations: {}", cycle.join(", "));
+    }
+    Ok(order)
+}
+
+/// Name an operation in messages by its id, or its manifest position.
+pub fn describe_op(ops: &[crate::model::Operation], index: usize) -> String {
+    match &ops[index].meta().id {
+        Some(id) => id.clone(),
+        None => format!("#{}", index + 1),
+    }
+}
+
+/// Expand composite operations into the primitive operations they stand for.
+///
+/// Expanded operations get ids of the form `<id>:<path>` and inherit the
+/// label, so the same composite operation always expands to the same ids.
+fn expand_operation(
+    root: &Path,
+    id: &str,
+    op: &crate::model::Operation,
+    touched: &Touched,
+) -> Result<Vec<(String, crate::model::Operation)>> {
+    let derive = |path: &Path| {
+        let id = format!("{}:{}", id, path.display());
+        let meta = crate::model::OpMeta {
+            id: Some(id.clone()),
+            label: op.meta().label.clone(),
+            after: Vec::new(),
+        };
+        (id, meta)
+    };
+    match op {
+        crate::model::Operation::MoveInto {
+            srcs,
+            dir,
+            create_dir,
+            overrides,
+            ..
+        } => {
+            let mut expanded = Vec::new();
+            // The directory may exist already, or be created by an earlier
+            // operation (e.g. a mkdir) that has not run yet.
+            let resolved = crate::resolve::resolve_path(root, dir)?;
+            if *create_dir && !resolved.exists() && !touched.covers(&resolved) {
+                let (id, meta) = derive(dir);
+                let mkdir = crate::model::Operation::Mkdir {
+                    dst: dir.clone(),
+                    parents: false,
+                    meta,
+                    overrides: overrides.clone(),
+                };
+                expanded.push((id, mkdir));
+            }
+            for src in srcs {
+                let name = src
+                    .file_name()
+                    .ok_or_else(|| anyhow::anyhow!("source has no file name: {}", src.display()))?;
+                let (id, meta) = derive(src);
+                let mv = crate::model::Operation::Move {
+                    src: src.clone(),
+                    dst: dir.join(name),
+                    cross_device: false,
+                    meta,
+                    overrides: overrides.clone(),
+                };
+                expanded.push((id, mv));
+            }
+            Ok(expanded)
+        }
+        crate::model::Operation::RenameBatch {
+            srcs,
+            pattern,
+            replacement,
+            overrides,
+            ..
+        } => Ok(rename_batch_targets(srcs, pattern, replacement)?
+            .into_iter()
+            .map(|(src, dst)| {
+                let (id, meta) = derive(&src);
+                let rename = crate::model::Operation::Rename {
+                    src,
+                    dst,
+                    meta,
+                    overrides: overrides.clone(),
+                };
+                (id, rename)
+            })
+            .collect()),
+        _ => Ok(vec![(id.to_string(), op.clone())]),
+    }
+}
+
+/// Compute `(src, dst)` pairs for a `rename_batch`, in source order.
+fn rename_batch_targets(
+    srcs: &[PathBuf],
+    pattern: &str,
+    replacement: &str,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let regex = regex::Regex::new(pattern)
+        .map_err(|e| anyhow::anyhow!("invalid rename_batch pattern: {}", e))?;
+    let mut targets = Vec::new();
+    for src in srcs {
+        let name = src
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow::anyhow!("source has no UTF-8 file name: {}", src.display()))?;
+        if !regex.is_match(name) {
+            anyhow::bail!("rename_batch pattern does not match: {}", src.display());
+        }
+        let renamed = regex.replace(name, replacement);
+        if renamed.is_empty() || renamed.contains('/') {
+            anyhow::bail!(
+                "invalid rename_batch result for {}: {:?}",
+                src.display(),
+                renamed
+            );
+        }
+        targets.push((src.clone(), src.with_file_name(renamed.as_ref())));
+    }
+    Ok(targets)
+}
+
 fn resolve_operation_paths(
     root: &Path,
     op: &crate::model::Operation,
@@ -47,15 +287,59 @@ fn resolve_operation_paths(
             let resolved_dst = crate::resolve::resolve_path(root, dst)?;
             Ok((Some(resolved_src), Some(resolved_dst)))
         }
-        crate::model::Operation::Rename { src, dst } => {
+        crate::model::Operation::Rename { src, dst, .. } => {
             let resolved_src = crate::resolve::resolve_path(root, src)?;
             let resolved_dst = crate::resolve::resolve_path(root, dst)?;
             Ok((Some(resolved_src), Some(resolved_dst)))
         }
-        crate::model::Operation::Trash { src } => {
+        crate::model::Operation::Trash { src, .. } => {
             let resolved_src = crate::resolve::resolve_path(root, src)?;
             Ok((Some(resolved_src), None))
         }
+        crate::model::Operation::Chmod { dst, .. }
+        | crate::model::Operation::Chown { dst, .. }
+        | crate::model::Operation::SetTimes { dst, .. }
+        | crate::model::Operation::WriteFile { dst, .. }
+        | crate::model::Operation::Edit { dst, .. }
+        | crate::model::Operation::SetXattr { dst, .. }
+        | crate::model::Operation::RemoveXattr { dst, .. }
+        | crate::model::Operation::Rmdir { dst, .. }
+        | crate::model::Operation::Archive { dst, .. } => {
+            let resolved = crate::resolve::resolve_path(root, dst)?;
+            Ok((None, Some(resolved)))
+        }
+        crate::model::Operation::Assert { path, .. } => {
+            let resolved = crate::resolve::resolve_entry(root, path)?;
+            Ok((None, Some(resolved)))
+        }
+        crate::model::Operation::MoveInto { .. } | crate::model::Operation::RenameBatch { .. } => {
+            anyhow::bail!("composite operations must be expanded before resolving paths")
+        }
+        crate::model::Operation::Exchange { a, b, .. } => {
+            let resolved_a = crate::resolve::resolve_path(root, a)?;
+            let resolved_b = crate::resolve::resolve_path(root, b)?;
+            Ok((Some(resolved_a), Some(resolved_b)))
+        }
+        crate::model::Operation::Hardlink { src, dst, .. }
+        | crate::model::Operation::Install { src, dst, .. }
+        | crate::model::Operation::Extract {
+            archive: src, dst, ..
+        } => {
+            let resolved_src = crate::resolve::resolve_path(root, src)?;
+            let resolved_dst = crate::resolve::resolve_path(root, dst)?;
+            Ok((Some(resolved_src), Some(resolved_dst)))
+        }
+        crate::model::Operation::Symlink {
+            target,
+            dst,
+            allow_external_target,
+            ..
+        } => {
+            let resolved_target =
+                crate::resolve::resolve_link_target(root, target, *allow_external_target)?;
+            let resolved_dst = crate::resolve::resolve_path(root, dst)?;
+            Ok((Some(resolved_target), Some(resolved_dst)))
+        }
     }
 }
 
@@ -83,68 +367,387 @@ fn compute_parent_dirs(dst: &Option<PathBuf>, op: &crate::model::Operation) -> V
 }
 
 /// Pre‑flight checks (e.g., source existence, permissions, free space).
-pub fn preflight_check(plan: &crate::model::Plan) -> Result<()> {
-    for op in &plan.operations {
-        match op {
-            crate::model::Operation::Mkdir { .. } => {}
-            crate::model::Operation::Move { src, .. }
-            | crate::model::Operation::Copy { src, .. }
-            | crate::model::Operation::Rename { src, .. }
-            | crate::model::Operation::Trash { src } => {
-                // Check for symlinks BEFORE canonicalization resolution to catch them
-                // We use resolve_path to ensure it doesn't escape, but we also check the raw path for policy
-                // Better: use normalize_lexical logic if exposed, or just simple check if it doesn't have ..?
-                // But src might be relative.
-                // Let's rely on resolve_path returning the canonical path for EXISTENCE/SAFETY.
-                // But for SYMLINK check, we need the path that points TO the symlink.
-                // If `src` is "link", `root.join(src)` is ".../link".
-                // We should check metadata of THAT.
-                // CAUTION: If `src` escapes root via `..`, `root.join` is unsafe?
-                // `resolve_path` checks for escape. If `resolve_path` succeeds, then `src` (resolved) is safe.
-                // But `resolved` is canonical.
-                // We need to verify `root.join(src)` is safe AND is the symlink.
-
-                // Let's do:
-                let resolved = crate::resolve::resolve_path(&plan.root, src)?;
-                if !resolved.exists() {
-                    anyhow::bail!("source does not exist: {}", resolved.display());
-                }
+/// `failed` is called with the manifest index of the operation that fails
+/// them, before its error is returned.
+pub fn preflight_check(
+    plan: &crate::model::Plan,
+    mut failed: impl FnMut(usize, &anyhow::Error),
+) -> Result<()> {
+    // Paths touched by earlier operations; assertions on these can only be
+    // evaluated once execution reaches them.
+    let mut touched = Touched::default();
+    for (index, _) in execution_order(plan)? {
+        let op = &plan.operations[index];
+        if let Err(e) = preflight_operation(plan, op, &touched) {
+            failed(index, &e);
+            return Err(e);
+        }
+        record_touched(&plan.root, op, &mut touched)?;
+    }
+    Ok(())
+}
+
+/// Paths of operations that run earlier but whose effects cannot be seen
+/// yet, indexed so that checking a path against them stays cheap.
+#[derive(Default)]
+pub struct Touched(std::collections::BTreeMap<PathBuf, ()>);
+
+impl Touched {
+    /// Whether `path` is, contains or lies inside any touched path.
+    fn overlaps(&self, path: &Path) -> bool {
+        overlapping(&self.0, path).next().is_some()
+    }
 
-                // Check symlink policy on the path segments?
-                // Or just on the immediate file pointed to by `src` relative to root?
-                // If `src` is "a/b", and "a" is a symlink?
-                // Confinement usually implies we don't care if intermediates are symlinks as long as they stay in root?
-                // `resolve_path` ensures confinement.
-                // `SymlinkPolicy` usually targets the LEAF? Or any part?
-                // Usually the file being operated on.
-
-                // Construct path we think it is:
-                let potential_link = plan.root.join(src);
-                // Verify it exists (it might be `..` normalized out, or `.`?)
-                // If we use `crate::resolve::resolve_path` without canonicalization?
-                // `resolve_path` is hardcoded to canonicalize.
-
-                // Let's try to check `symlink_metadata` on `potential_link`.
-                // Note: `potential_link` might have `..`.
-                // If we `canonicalize` potential_link, we lose the link.
-                // We just want to know if it IS a link.
-                // `std::fs::symlink_metadata` works on paths with `..`.
-
-                if let Ok(meta) = std::fs::symlink_metadata(&potential_link)
-                    && meta.file_type().is_symlink()
+    /// Whether `path` is or contains a touched path, so that it exists once
+    /// the earlier operations have run.
+    fn covers(&self, path: &Path) -> bool {
+        self.0
+            .range::<Path, _>((std::ops::Bound::Included(path), std::ops::Bound::Unbounded))
+            .next()
+            .is_some_and(|(key, _)| key.starts_with(path))
+    }
+}
+
+/// Add the paths `op` may change to `touched`, for preflighting the
+/// operations after it without running it.
+pub fn record_touched(
+    root: &Path,
+    op: &crate::model::Operation,
+    touched: &mut Touched,
+) -> Result<()> {
+    if matches!(op, crate::model::Operation::Assert { .. }) {
+        return Ok(());
+    }
+    for path in op.paths() {
+        touched
+            .0
+            .insert(crate::resolve::resolve_entry(root, path)?, ());
+        touched
+            .0
+            .insert(crate::resolve::resolve_path(root, path)?, ());
+    }
+    Ok(())
+}
+
+/// Pre-flight checks for a single operation. `touched` holds the paths of
+/// operations that run before it, whose effects cannot be seen yet.
+pub fn preflight_operation(
+    plan: &crate::model::Plan,
+    op: &crate::model::Operation,
+    touched: &Touched,
+) -> Result<()> {
+    match op {
+        crate::model::Operation::Mkdir { .. }
+        | crate::model::Operation::Symlink { .. }
+        | crate::model::Operation::WriteFile { .. } => {}
+        crate::model::Operation::Move { src, dst, .. }
+        | crate::model::Operation::Rename { src, dst, .. } => {
+            if let Some(resolved) = check_existing_source(plan, op, src, touched)?
+                && !plan.allow_cross_device_for(op)
+            {
+                check_same_device(plan, &resolved, dst)?;
+            }
+        }
+        crate::model::Operation::Copy { src, .. } | crate::model::Operation::Trash { src, .. } => {
+            check_existing_source(plan, op, src, touched)?;
+        }
+        crate::model::Operation::Chmod { dst, .. }
+        | crate::model::Operation::SetXattr { dst, .. }
+        | crate::model::Operation::RemoveXattr { dst, .. } => {
+            check_existing_source(plan, op, dst, touched)?;
+        }
+        crate::model::Operation::Chown {
+            dst, user, group, ..
+        } => {
+            let uid = user.as_deref().map(crate::fsops::lookup_user).transpose()?;
+            let gid = group
+                .as_deref()
+                .map(crate::fsops::lookup_group)
+                .transpose()?;
+            if let Some(resolved) = check_existing_source(plan, op, dst, touched)? {
+                crate::policy::check_chown_privilege(&resolved, uid, gid)?;
+            }
+        }
+        crate::model::Operation::SetTimes {
+            dst,
+            create_if_missing,
+            ..
+        } => {
+            if !*create_if_missing {
+                check_existing_source(plan, op, dst, touched)?;
+            }
+        }
+        crate::model::Operation::Install { src, .. } => {
+            if let Some(resolved) = check_existing_source(plan, op, src, touched)?
+                && !resolved.is_file()
+            {
+                anyhow::bail!(
+                    "install source is not a regular file: {}",
+                    resolved.display()
+                );
+            }
+        }
+        crate::model::Operation::Extract {
+            archive,
+            dst,
+            strip_components,
+            ..
+        } => {
+            // An archive written earlier in the plan cannot be listed yet.
+            let Some(resolved) = check_existing_source(plan, op, archive, touched)? else {
+                return Ok(());
+            };
+            for member in crate::fsops::archive_members(&resolved, *strip_components)? {
+                let target = crate::resolve::resolve_path(&plan.root, &dst.join(&member.path))?;
+                if !member.is_dir && std::fs::symlink_metadata(&target).is_ok() {
+                    anyhow::bail!("extract target already exists: {}", target.display());
+                }
+            }
+        }
+        crate::model::Operation::Archive { srcs, .. } => {
+            for src in srcs {
+                let entry = crate::resolve::resolve_entry(&plan.root, src)?;
+                if touched.overlaps(&entry) {
+                    continue;
+                }
+                if std::fs::symlink_metadata(&entry).is_err() {
+                    anyhow::bail!("source does not exist: {}", entry.display());
+                }
+                // Skipped symlinks are simply left out of the archive.
+                let symlink_policy = plan.symlink_policy_for(op);
+                if symlink_policy != crate::model::SymlinkPolicy::Skip {
+                    crate::policy::handle_symlink(symlink_policy, &entry)?;
+                }
+            }
+        }
+        crate::model::Operation::MoveInto { srcs, dir, .. } => {
+            for src in srcs {
+                if let Some(resolved) = check_existing_source(plan, op, src, touched)?
+                    && !plan.allow_cross_device_for(op)
                 {
-                    // It is a symlink! Check policy.
-                    crate::policy::handle_symlink(plan.symlink_policy, &potential_link)?;
+                    check_same_device(plan, &resolved, dir)?;
                 }
-
-                // Also check `resolved` just in case (e.g. if src was "." and root was symlink?)
-                // But `handle_symlink` on resolved (target) passes if target is file.
+            }
+            let resolved = crate::resolve::resolve_path(&plan.root, dir)?;
+            if resolved.exists() && !resolved.is_dir() {
+                anyhow::bail!(
+                    "move_into target is not a directory: {}",
+                    resolved.display()
+                );
+            }
+        }
+        crate::model::Operation::RenameBatch {
+            srcs,
+            pattern,
+            replacement,
+            ..
+        } => {
+            let mut resolved = Vec::new();
+            for (src, dst) in rename_batch_targets(srcs, pattern, replacement)? {
+                check_existing_source(plan, op, &src, touched)?;
+                resolved.push((
+                    crate::resolve::resolve_entry(&plan.root, &src)?,
+                    crate::resolve::resolve_entry(&plan.root, &dst)?,
+                ));
+            }
+            for (i, (src, dst)) in resolved.iter().enumerate() {
+                if let Some((other, _)) = resolved[..i].iter().find(|(_, d)| d == dst) {
+                    anyhow::bail!(
+                        "rename_batch maps {} and {} to the same destination: {}",
+                        other.display(),
+                        src.display(),
+                        dst.display()
+                    );
+                }
+                if let Some((later, _)) = resolved[i + 1..].iter().find(|(s, _)| s == dst) {
+                    anyhow::bail!(
+                        "rename_batch destination of {} collides with later source {}",
+                        src.display(),
+                        later.display()
+                    );
+                }
+            }
+        }
+        crate::model::Operation::Assert { path, .. } => {
+            let resolved = crate::resolve::resolve_entry(&plan.root, path)?;
+            if !touched.overlaps(&resolved) {
+                crate::assertion::check(&plan.root, op)?;
+            }
+        }
+        crate::model::Operation::Edit {
+            dst,
+            find,
+            replace,
+            count,
+            regex,
+            ..
+        } => {
+            let resolved = crate::resolve::resolve_path(&plan.root, dst)?;
+            // Files touched earlier (e.g. just renamed into place) are only
+            // checked when execution reaches the edit.
+            if !touched.overlaps(&resolved) {
+                let resolved = check_source(plan, op, dst)?;
+                if !resolved.is_file() {
+                    anyhow::bail!("edit target is not a regular file: {}", resolved.display());
+                }
+                crate::fsops::edited_content(&resolved, find, replace, *regex, *count)?;
+            }
+        }
+        crate::model::Operation::Rmdir { dst, .. } => {
+            // Earlier operations may create the directory or empty it (e.g.
+            // by moving its files out), so only execution can tell.
+            if let Some(resolved) = check_existing_source(plan, op, dst, touched)? {
+                if !resolved.is_dir() {
+                    anyhow::bail!("rmdir target is not a directory: {}", resolved.display());
+                }
+                if std::fs::read_dir(&resolved)?.next().is_some() {
+                    anyhow::bail!("directory not empty: {}", resolved.display());
+                }
+            }
+        }
+        crate::model::Operation::Exchange { a, b, .. } => {
+            check_existing_source(plan, op, a, touched)?;
+            check_existing_source(plan, op, b, touched)?;
+            let resolved_a = crate::resolve::resolve_path(&plan.root, a)?;
+            if resolved_a == crate::resolve::resolve_path(&plan.root, b)? {
+                anyhow::bail!(
+                    "cannot exchange a path with itself: {}",
+                    resolved_a.display()
+                );
+            }
+        }
+        crate::model::Operation::Hardlink { src, dst, .. } => {
+            let Some(resolved) = check_existing_source(plan, op, src, touched)? else {
+                return Ok(());
+            };
+            if resolved.is_dir() {
+                anyhow::bail!("cannot hardlink a directory: {}", resolved.display());
+            }
+            let resolved_dst = crate::resolve::resolve_path(&plan.root, dst)?;
+            if !crate::fsops::same_device(&resolved, existing_ancestor(plan, &resolved_dst))? {
+                anyhow::bail!(
+                    "hardlink source and destination are on different devices: {} -> {}",
+                    resolved.display(),
+                    resolved_dst.display()
+                );
             }
         }
     }
     Ok(())
 }
 
+/// Nearest existing ancestor of `path`, which may not exist yet (e.g. when
+/// created by an earlier mkdir).
+fn existing_ancestor<'a>(plan: &'a crate::model::Plan, path: &'a Path) -> &'a Path {
+    path.ancestors()
+        .skip(1)
+        .find(|p| p.exists())
+        .unwrap_or(plan.root.as_path())
+}
+
+/// `check_source`, unless an earlier operation touches `src` (e.g. moves
+/// or copies it into place); then it may not exist until execution, which
+/// checks it instead and `None` is returned.
+fn check_existing_source(
+    plan: &crate::model::Plan,
+    op: &crate::model::Operation,
+    src: &Path,
+    touched: &Touched,
+) -> Result<Option<PathBuf>> {
+    if touched.overlaps(&crate::resolve::resolve_path(&plan.root, src)?) {
+        return Ok(None);
+    }
+    check_source(plan, op, src).map(Some)
+}
+
+/// Reject a move of `src` to `dst` that would cross devices.
+fn check_same_device(plan: &crate::model::Plan, src: &Path, dst: &Path) -> Result<()> {
+    let resolved_dst = crate::resolve::resolve_path(&plan.root, dst)?;
+    if !crate::fsops::same_device(src, existing_ancestor(plan, &resolved_dst))? {
+        return Err(crate::policy::PolicyViolation(format!(
+            "cross-device move not allowed: {} -> {}",
+            src.display(),
+            resolved_dst.display()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Values of the keys of `index` that are `path`, an ancestor of it, or
+/// inside it. Paths order component by component, so everything inside
+/// `path` sorts directly after it.
+fn overlapping<'a, V>(
+    index: &'a std::collections::BTreeMap<PathBuf, V>,
+    path: &'a Path,
+) -> impl Iterator<Item = &'a V> {
+    use std::ops::Bound;
+    let inside = index
+        .range::<Path, _>((Bound::Excluded(path), Bound::Unbounded))
+        .take_while(move |(key, _)| key.starts_with(path))
+        .map(|(_, value)| value);
+    path.ancestors()
+        .filter_map(|ancestor| index.get(ancestor))
+        .chain(inside)
+}
+
+/// Check that a source exists and satisfies the symlink policy.
+fn check_source(
+    plan: &crate::model::Plan,
+    op: &crate::model::Operation,
+    src: &Path,
+) -> Result<PathBuf> {
+    // Check for symlinks BEFORE canonicalization resolution to catch them
+    // We use resolve_path to ensure it doesn't escape, but we also check the raw path for policy
+    // Better: use normalize_lexical logic if exposed, or just simple check if it doesn't have ..?
+    // But src might be relative.
+    // Let's rely on resolve_path returning the canonical path for EXISTENCE/SAFETY.
+    // But for SYMLINK check, we need the path that points TO the symlink.
+    // If `src` is "link", `root.join(src)` is ".../link".
+    // We should check metadata of THAT.
+    // CAUTION: If `src` escapes root via `..`, `root.join` is unsafe?
+    // `resolve_path` checks for escape. If `resolve_path` succeeds, then `src` (resolved) is safe.
+    // But `resolved` is canonical.
+    // We need to verify `root.join(src)` is safe AND is the symlink.
+
+    // Let's do:
+    let resolved = crate::resolve::resolve_path(&plan.root, src)?;
+    if !resolved.exists() {
+        anyhow::bail!("source does not exist: {}", resolved.display());
+    }
+
+    // Check symlink policy on the path segments?
+    // Or just on the immediate file pointed to by `src` relative to root?
+    // If `src` is "a/b", and "a" is a symlink?
+    // Confinement usually implies we don't care if intermediates are symlinks as long as they stay in root?
+    // `resolve_path` ensures confinement.
+    // `SymlinkPolicy` usually targets the LEAF? Or any part?
+    // Usually the file being operated on.
+
+    // Construct path we think it is:
+    let potential_link = plan.root.join(src);
+    // Verify it exists (it might be `..` normalized out, or `.`?)
+    // If we use `crate::resolve::resolve_path` without canonicalization?
+    // `resolve_path` is hardcoded to canonicalize.
+
+    // Let's try to check `symlink_metadata` on `potential_link`.
+    // Note: `potential_link` might have `..`.
+    // If we `canonicalize` potential_link, we lose the link.
+    // We just want to know if it IS a link.
+    // `std::fs::symlink_metadata` works on paths with `..`.
+
+    if let Ok(meta) = std::fs::symlink_metadata(&potential_link)
+        && meta.file_type().is_symlink()
+    {
+        // It is a symlink! Check policy.
+        crate::policy::handle_symlink(plan.symlink_policy_for(op), &potential_link)?;
+    }
+
+    // Also check `resolved` just in case (e.g. if src was "." and root was symlink?)
+    // But `handle_symlink` on resolved (target) passes if target is file.
+    Ok(resolved)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -155,6 +758,8 @@ mod tests {
         let op = crate::model::Operation::Mkdir {
             dst: PathBuf::from("a/b"),
             parents: true,
+            overrides: Default::default(),
+            meta: Default::default(),
         };
         // Use a dummy root that exists (tempdir) to avoid resolve error if it checks existence?
         // normalize_plan calls resolve_path. resolve_path checks canonicalization of root.
@@ -169,6 +774,11 @@ mod tests {
             symlink_policy: crate::model::SymlinkPolicy::Error,
             allow_overwrite: false,
             operations: vec![op.clone()],
+            allow_cross_device: true,
+            vars: Default::default(),
+            version: crate::model::CURRENT_VERSION,
+            include: Vec::new(),
+            sources: Vec::new(),
         };
 
         let a_ops = normalize_plan(&plan).unwrap();
@@ -180,17 +790,86 @@ mod tests {
         let a = &a_ops[0];
         let b = &b_ops[0];
 
+        // Without a manifest id, the op is named by its position.
+        assert_eq!(a.id, "#1");
+        assert_eq!(a.id, b.id);
         assert_eq!(format!("{:?}", a.op), format!("{:?}", b.op));
         assert_eq!(a.resolved_src, b.resolved_src);
         assert_eq!(a.resolved_dst, b.resolved_dst);
         assert_eq!(a.parents, b.parents);
     }
 
+    #[test]
+    fn overlapping_finds_ancestors_and_contents_only() {
+        let index: std::collections::BTreeMap<PathBuf, &str> = [
+            ("/r/a", "a"),
+            ("/r/a/b", "b"),
+            ("/r/a/b/c", "c"),
+            ("/r/a/b-x", "sibling"),
+            ("/r/a/bb", "prefix"),
+        ]
+        .into_iter()
+        .map(|(path, name)| (PathBuf::from(path), name))
+        .collect();
+        let found = |path: &str| {
+            let mut names: Vec<_> = overlapping(&index, Path::new(path)).copied().collect();
+            names.sort();
+            names
+        };
+        assert_eq!(found("/r/a/b"), ["a", "b", "c"]);
+        assert_eq!(found("/r/a/b/c/d"), ["a", "b", "c"]);
+        assert!(found("/r/z").is_empty());
+    }
+
+    #[test]
+    fn move_into_expands_with_stable_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let op = crate::model::Operation::MoveInto {
+            srcs: vec![PathBuf::from("a.txt"), PathBuf::from("sub/b.txt")],
+            dir: PathBuf::from("out"),
+            create_dir: true,
+            overrides: Default::default(),
+            meta: Default::default(),
+        };
+        let first = expand_operation(dir.path(), "archive", &op, &Touched::default()).unwrap();
+        let second = expand_operation(dir.path(), "archive", &op, &Touched::default()).unwrap();
+
+        let ids: Vec<_> = first.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(
+            ids,
+            second.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(ids, ["archive:out", "archive:a.txt", "archive:sub/b.txt"]);
+        assert!(
+            matches!(&first[0].1, crate::model::Operation::Mkdir { dst, .. } if dst == Path::new("out"))
+        );
+        assert!(
+            matches!(&first[2].1, crate::model::Operation::Move { dst, .. } if dst == Path::new("out/b.txt"))
+        );
+    }
+
+    #[test]
+    fn rename_batch_applies_capture_groups_to_file_names() {
+        let srcs = vec![
+            PathBuf::from("photos/IMG_1234.JPG"),
+            PathBuf::from("IMG_0007.JPG"),
+        ];
+        let targets =
+            rename_batch_targets(&srcs, r"^IMG_(\d+)\.JPG$", "2025-01-02_${1}.jpg").unwrap();
+        assert_eq!(targets[0].1, PathBuf::from("photos/2025-01-02_1234.jpg"));
+        assert_eq!(targets[1].1, PathBuf::from("2025-01-02_0007.jpg"));
+
+        let err = rename_batch_targets(&srcs, r"^DSC_", "x").unwrap_err();
+        assert!(err.to_string().contains("does not match"));
+    }
+
     #[test]
     fn test_compute_parent_dirs() {
         let op = crate::model::Operation::Mkdir {
             dst: PathBuf::from("a/b/c"),
             parents: true,
+            overrides: Default::default(),
+            meta: Default::default(),
         };
         let _dst = Some(PathBuf::from("/root/a/b/c"));
 
@@ -209,4 +888,27 @@ mod tests {
         assert_eq!(parents_2.len(), 1);
         assert_eq!(parents_2[0], root.join("a/b"));
     }
+
+    #[test]
+    fn depends_on_lists_expanded_ids_of_dependencies() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        std::fs::write(root.join("a.txt"), "a").unwrap();
+
+        let plan: crate::model::Plan = serde_json::from_value(serde_json::json!({
+            "root": root,
+            "operations": [
+                { "op": "move_into", "srcs": ["a.txt"], "dir": "out", "create_dir": true, "id": "file" },
+                { "op": "mkdir", "dst": "other", "id": "other" },
+                { "op": "rename", "src": "out/a.txt", "dst": "out/b.txt", "id": "rename" }
+            ]
+        }))
+        .unwrap();
+
+        let ops = normalize_plan(&plan).unwrap();
+        let ids: Vec<&str> = ops.iter().map(|op| op.id.as_str()).collect();
+        assert_eq!(ids, ["file:out", "file:a.txt", "other", "rename"]);
+        assert!(ops[2].depends_on.is_empty());
+        assert_eq!(ops[3].depends_on, ["file:out", "file:a.txt"]);
+    }
 }
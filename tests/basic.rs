@@ -31,4 +31,5 @@ fn test_schema_generation() {
     let schema = tfs::model::generate_schema();
     assert!(schema.contains("$schema"));
     assert!(schema.contains("Plan"));
+    assert!(schema.contains("JSON, YAML or TOML"));
 }
@@ -40,6 +40,7 @@ fn test_apply_mkdir_move() -> Result<()> {
         collision_policy: None,
         root: Some(root.clone()),
         allow_overwrite: false,
+        format: None,
     };
 
     let exit_code = tfs::engine::apply(args)?;
@@ -79,6 +80,7 @@ fn test_overwrite_with_backup() -> Result<()> {
         collision_policy: Some(CollisionPolicy::OverwriteWithBackup),
         root: Some(root.clone()),
         allow_overwrite: true,
+        format: None,
     };
 
     let exit_code = tfs::engine::apply(args)?;
@@ -196,6 +198,7 @@ fn test_symlink_policies_follow_and_skip() -> Result<()> {
             collision_policy: None,
             root: Some(root.clone()),
             allow_overwrite: false,
+            format: None,
         };
 
         // Should succeed: "link.txt" resolves to "target.txt".
@@ -239,6 +242,7 @@ fn test_symlink_policies_follow_and_skip() -> Result<()> {
             collision_policy: None,
             root: Some(root.clone()),
             allow_overwrite: false,
+            format: None,
         };
 
         // Current implementation of Skip returns an Error ("symlink skipped").
@@ -278,6 +282,7 @@ fn test_validate_only_mode() -> Result<()> {
         collision_policy: None,
         root: Some(root.clone()),
         allow_overwrite: false,
+        format: None,
     };
 
     // Capture stdout manually if we want to check for PlanValidated event.
@@ -333,6 +338,7 @@ fn test_transaction_mode_op() -> Result<()> {
         collision_policy: Some(CollisionPolicy::Fail),
         root: Some(root.clone()),
         allow_overwrite: false,
+        format: None,
     };
 
     // Should return success or failure?
@@ -385,6 +391,7 @@ fn test_dry_run_produces_no_fs_or_journal_writes() -> Result<()> {
         collision_policy: None,
         root: Some(root.clone()),
         allow_overwrite: false,
+        format: None,
     };
 
     let exit_code = tfs::engine::apply(args)?;
@@ -442,6 +449,7 @@ fn test_rollback_on_failure() -> Result<()> {
         collision_policy: None,
         root: Some(root.clone()),
         allow_overwrite: false,
+        format: None,
     };
 
     // Expect failure
@@ -485,6 +493,7 @@ fn test_undo_command() -> Result<()> {
         collision_policy: None,
         root: Some(root.clone()),
         allow_overwrite: false,
+        format: None,
     };
 
     let exit_code = tfs::engine::apply(args)?;
@@ -530,6 +539,7 @@ fn test_dry_run() -> Result<()> {
         collision_policy: None,
         root: Some(root.clone()),
         allow_overwrite: false,
+        format: None,
     };
 
     let exit_code = tfs::engine::apply(args)?;
@@ -566,6 +576,7 @@ fn test_collision_overwrite_behavior() -> Result<()> {
         collision_policy: Some(CollisionPolicy::Fail),
         root: Some(root.clone()),
         allow_overwrite: false,
+        format: None,
     };
 
     let result = tfs::engine::apply(args);
@@ -606,6 +617,7 @@ fn test_symlink_policy_error() -> Result<()> {
         collision_policy: None,
         root: Some(root.clone()),
         allow_overwrite: false,
+        format: None,
     };
 
     // Should fail because default SymlinkPolicy is Error
@@ -640,6 +652,7 @@ fn test_recursive_copy() -> Result<()> {
         collision_policy: None,
         root: Some(root.clone()),
         allow_overwrite: false,
+        format: None,
     };
 
     let exit_code = tfs::engine::apply(args)?;
@@ -672,6 +685,7 @@ fn test_trash_op() -> Result<()> {
         collision_policy: None,
         root: Some(root.clone()),
         allow_overwrite: false,
+        format: None,
     };
 
     let exit_code = tfs::engine::apply(args)?;
@@ -682,3 +696,2819 @@ fn test_trash_op() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+#[cfg(unix)]
+fn test_symlink_to_symlink_keeps_the_intermediate_link() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().canonicalize()?;
+
+    fs::create_dir_all(root.join("releases/v1"))?;
+    fs::create_dir_all(root.join("releases/v2"))?;
+    std::os::unix::fs::symlink(root.join("releases/v1"), root.join("current"))?;
+
+    let ops = json!([
+        { "op": "symlink", "target": "current", "dst": "live" },
+        { "op": "symlink", "target": "current", "dst": "live-rel", "relative": true }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 0);
+
+    assert_eq!(fs::read_link(root.join("live"))?, root.join("current"));
+    assert_eq!(
+        fs::read_link(root.join("live-rel"))?,
+        PathBuf::from("current")
+    );
+
+    // Retargeting `current` moves both links along with it.
+    fs::remove_file(root.join("current"))?;
+    std::os::unix::fs::symlink(root.join("releases/v2"), root.join("current"))?;
+    assert_eq!(
+        fs::canonicalize(root.join("live"))?,
+        root.join("releases/v2")
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_symlink_op_and_undo() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().canonicalize()?;
+    let journal_path = root.join("journal.jsonl");
+
+    fs::create_dir(root.join("new"))?;
+    fs::write(root.join("new/data.txt"), "data")?;
+
+    let ops = json!([
+        { "op": "symlink", "target": "new/data.txt", "dst": "old.txt", "relative": true },
+        { "op": "symlink", "target": "new", "dst": "legacy" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(
+        fs::read_link(root.join("old.txt"))?,
+        PathBuf::from("new/data.txt")
+    );
+    assert_eq!(fs::read_link(root.join("legacy"))?, root.join("new"));
+    assert_eq!(fs::read_to_string(root.join("old.txt"))?, "data");
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    assert!(fs::symlink_metadata(root.join("old.txt")).is_err());
+    assert!(fs::symlink_metadata(root.join("legacy")).is_err());
+    assert_eq!(fs::read_to_string(root.join("new/data.txt"))?, "data");
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_symlink_over_backup_restores_original() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().canonicalize()?;
+    let journal_path = root.join("journal.jsonl");
+
+    fs::write(root.join("data.txt"), "data")?;
+    fs::write(root.join("current.txt"), "original")?;
+
+    let apply = |ops: serde_json::Value, journal: Option<PathBuf>| {
+        let args = ApplyArgs {
+            manifest: create_manifest(&root, ops),
+            validate_only: false,
+            dry_run: false,
+            json: false,
+            journal,
+            collision_policy: Some(CollisionPolicy::OverwriteWithBackup),
+            root: Some(root.clone()),
+            allow_overwrite: true,
+            format: None,
+        };
+        tfs::engine::apply(args)
+    };
+
+    // Rollback: the failing assert undoes the symlink and restores the file.
+    let result = apply(
+        json!([
+            { "op": "symlink", "target": "data.txt", "dst": "current.txt" },
+            { "op": "assert", "path": "current.txt", "size": 1 }
+        ]),
+        None,
+    );
+    assert_eq!(result?, 2);
+    assert!(!fs::symlink_metadata(root.join("current.txt"))?.is_symlink());
+    assert_eq!(fs::read_to_string(root.join("current.txt"))?, "original");
+    assert!(!root.join("current.txt.backup").exists());
+
+    // Undo from the journal does the same.
+    let exit_code = apply(
+        json!([{ "op": "symlink", "target": "data.txt", "dst": "current.txt" }]),
+        Some(journal_path.clone()),
+    )?;
+    assert_eq!(exit_code, 0);
+    assert!(fs::symlink_metadata(root.join("current.txt"))?.is_symlink());
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+    assert!(!fs::symlink_metadata(root.join("current.txt"))?.is_symlink());
+    assert_eq!(fs::read_to_string(root.join("current.txt"))?, "original");
+    assert!(!root.join("current.txt.backup").exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_symlink_target_confined_to_root() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let outside = tempdir()?;
+
+    let ops = json!([
+        { "op": "symlink", "target": outside.path().to_str().unwrap(), "dst": "escape" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let result = tfs::engine::apply(args);
+    assert!(result.unwrap_err().to_string().contains("escapes root"));
+    assert!(fs::symlink_metadata(root.join("escape")).is_err());
+
+    // Explicitly allowed external targets are created as-is.
+    let ops = json!([
+        {
+            "op": "symlink",
+            "target": outside.path().to_str().unwrap(),
+            "dst": "escape",
+            "allow_external_target": true
+        }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+    assert_eq!(fs::read_link(root.join("escape"))?, outside.path());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_hardlink_op_and_undo() -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::write(root.join("artefact.bin"), "payload")?;
+
+    let ops = json!([
+        { "op": "mkdir", "dst": "dedup" },
+        { "op": "hardlink", "src": "artefact.bin", "dst": "dedup/artefact.bin" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    let original = fs::metadata(root.join("artefact.bin"))?;
+    let link = fs::metadata(root.join("dedup/artefact.bin"))?;
+    assert_eq!(original.ino(), link.ino());
+    assert_eq!(original.nlink(), 2);
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    assert!(!root.join("dedup").exists());
+    let original = fs::metadata(root.join("artefact.bin"))?;
+    assert_eq!(original.nlink(), 1);
+    assert_eq!(fs::read_to_string(root.join("artefact.bin"))?, "payload");
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_hardlink_over_backup_and_undo() -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::write(root.join("artefact.bin"), "payload")?;
+    fs::write(root.join("copy.bin"), "stale")?;
+
+    let ops = json!([
+        { "op": "hardlink", "src": "artefact.bin", "dst": "copy.bin" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: Some(CollisionPolicy::OverwriteWithBackup),
+        root: Some(root.clone()),
+        allow_overwrite: true,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+    assert_eq!(
+        fs::metadata(root.join("copy.bin"))?.ino(),
+        fs::metadata(root.join("artefact.bin"))?.ino()
+    );
+    assert_eq!(fs::read_to_string(root.join("copy.bin.backup"))?, "stale");
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(fs::read_to_string(root.join("copy.bin"))?, "stale");
+    assert!(!root.join("copy.bin.backup").exists());
+    assert_eq!(fs::metadata(root.join("artefact.bin"))?.nlink(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_hardlink_rejects_directory() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::create_dir(root.join("folder"))?;
+
+    let ops = json!([
+        { "op": "hardlink", "src": "folder", "dst": "folder_link" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: true,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let result = tfs::engine::apply(args);
+    assert!(
+        result
+            .unwrap_err()
+            .to_string()
+            .contains("cannot hardlink a directory")
+    );
+    assert!(!root.join("folder_link").exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_chmod_recursive_and_undo() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::create_dir(root.join("site"))?;
+    fs::write(root.join("site/index.html"), "<html>")?;
+    fs::write(root.join("site/app.js"), "js")?;
+    fs::set_permissions(root.join("site"), fs::Permissions::from_mode(0o700))?;
+    fs::set_permissions(
+        root.join("site/index.html"),
+        fs::Permissions::from_mode(0o600),
+    )?;
+    fs::set_permissions(root.join("site/app.js"), fs::Permissions::from_mode(0o640))?;
+
+    let ops = json!([
+        { "op": "chmod", "dst": "site", "mode": "0755", "recursive": true }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    let mode = |p: &str| fs::metadata(root.join(p)).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode("site"), 0o755);
+    assert_eq!(mode("site/index.html"), 0o755);
+    assert_eq!(mode("site/app.js"), 0o755);
+
+    // Every touched path is listed explicitly in the journal.
+    let entries = tfs::journal::read_journal(journal_path.clone())?;
+    let changes = entries
+        .iter()
+        .find_map(|e| match &e.undo {
+            Some(tfs::journal::UndoMetadata::Chmod { previous_modes }) => Some(previous_modes),
+            _ => None,
+        })
+        .expect("chmod undo metadata");
+    assert_eq!(changes.len(), 3);
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(mode("site"), 0o700);
+    assert_eq!(mode("site/index.html"), 0o600);
+    assert_eq!(mode("site/app.js"), 0o640);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_chmod_rolled_back_on_failure() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::write(root.join("script.sh"), "#!/bin/sh")?;
+    fs::set_permissions(root.join("script.sh"), fs::Permissions::from_mode(0o644))?;
+    fs::write(root.join("a.txt"), "A")?;
+    fs::write(root.join("b.txt"), "B")?;
+
+    let ops = json!([
+        { "op": "chmod", "dst": "script.sh", "mode": "0755" },
+        { "op": "move", "src": "a.txt", "dst": "b.txt" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_ne!(exit_code, 0);
+
+    let mode = fs::metadata(root.join("script.sh"))?.permissions().mode() & 0o7777;
+    assert_eq!(mode, 0o644);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_chown_and_undo() -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::write(root.join("service.conf"), "conf")?;
+    let before = fs::metadata(root.join("service.conf"))?;
+    let is_root = unsafe { libc::geteuid() } == 0;
+    // Unprivileged processes may only "change" to the current owner.
+    let (uid, gid) = if is_root {
+        (65534, 65534)
+    } else {
+        (before.uid(), before.gid())
+    };
+
+    let ops = json!([
+        { "op": "chown", "dst": "service.conf", "user": uid.to_string(), "group": gid.to_string() }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    let after = fs::metadata(root.join("service.conf"))?;
+    assert_eq!((after.uid(), after.gid()), (uid, gid));
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    let restored = fs::metadata(root.join("service.conf"))?;
+    assert_eq!(
+        (restored.uid(), restored.gid()),
+        (before.uid(), before.gid())
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_chown_without_privilege_is_policy_failure() -> Result<()> {
+    if unsafe { libc::geteuid() } == 0 {
+        return Ok(()); // root may chown anything
+    }
+
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::write(root.join("file.txt"), "data")?;
+
+    let ops = json!([
+        { "op": "chown", "dst": "file.txt", "user": "0" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_tfs"))
+        .arg("apply")
+        .arg("--manifest")
+        .arg(manifest)
+        .output()?;
+    assert_eq!(output.status.code(), Some(2));
+
+    Ok(())
+}
+
+#[test]
+fn test_set_times_and_undo() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::write(root.join("photo.jpg"), "jpeg")?;
+    let original_mtime = filetime::FileTime::from_unix_time(1_600_000_000, 123_456_789);
+    let original_atime = filetime::FileTime::from_unix_time(1_600_000_100, 987_654_321);
+    filetime::set_file_times(root.join("photo.jpg"), original_atime, original_mtime)?;
+
+    let ops = json!([
+        {
+            "op": "set_times",
+            "dst": "photo.jpg",
+            "mtime": "2025-01-02T03:04:05Z",
+            "atime": "2025-01-02T05:04:05+02:00"
+        },
+        { "op": "set_times", "dst": "new.txt", "mtime": "2020-01-01T00:00:00Z", "create_if_missing": true }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    let meta = fs::metadata(root.join("photo.jpg"))?;
+    let mtime = filetime::FileTime::from_last_modification_time(&meta);
+    let atime = filetime::FileTime::from_last_access_time(&meta);
+    assert_eq!(mtime.unix_seconds(), 1_735_787_045);
+    assert_eq!(atime.unix_seconds(), 1_735_787_045);
+    let meta = fs::metadata(root.join("new.txt"))?;
+    assert_eq!(
+        filetime::FileTime::from_last_modification_time(&meta).unix_seconds(),
+        1_577_836_800
+    );
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    let meta = fs::metadata(root.join("photo.jpg"))?;
+    assert_eq!(
+        filetime::FileTime::from_last_modification_time(&meta),
+        original_mtime
+    );
+    assert_eq!(
+        filetime::FileTime::from_last_access_time(&meta),
+        original_atime
+    );
+    assert!(!root.join("new.txt").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_set_times_missing_file_fails_preflight() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    let ops = json!([
+        { "op": "set_times", "dst": "missing.txt", "mtime": "2025-01-02T03:04:05Z" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let result = tfs::engine::apply(args);
+    assert!(result.unwrap_err().to_string().contains("does not exist"));
+    assert!(!root.join("missing.txt").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_write_file_with_backup_and_undo() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::create_dir(root.join("docs"))?;
+    fs::write(root.join("docs/README.md"), "old readme")?;
+
+    let ops = json!([
+        { "op": "write_file", "dst": "docs/README.md", "content": "# Docs\n" },
+        { "op": "write_file", "dst": "docs/.keep", "content_base64": "AAEC" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: Some(CollisionPolicy::OverwriteWithBackup),
+        root: Some(root.clone()),
+        allow_overwrite: true,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(fs::read_to_string(root.join("docs/README.md"))?, "# Docs\n");
+    assert_eq!(
+        fs::read_to_string(root.join("docs/README.md.backup"))?,
+        "old readme"
+    );
+    assert_eq!(fs::read(root.join("docs/.keep"))?, vec![0u8, 1, 2]);
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(
+        fs::read_to_string(root.join("docs/README.md"))?,
+        "old readme"
+    );
+    assert!(!root.join("docs/README.md.backup").exists());
+    assert!(!root.join("docs/.keep").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_write_file_undo_keeps_modified_file() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    let ops = json!([
+        { "op": "write_file", "dst": "config.toml", "content": "a = 1\n" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    // Someone edits the file after tfs wrote it.
+    fs::write(root.join("config.toml"), "a = 2\n")?;
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let result = tfs::engine::undo(undo_args);
+    assert!(result.unwrap_err().to_string().contains("content changed"));
+    assert_eq!(fs::read_to_string(root.join("config.toml"))?, "a = 2\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_write_file_requires_exactly_one_content() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    let ops = json!([
+        { "op": "write_file", "dst": "a.txt", "content": "x", "content_base64": "eA==" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: true,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let result = tfs::engine::apply(args);
+    assert!(format!("{:#}", result.unwrap_err()).contains("exactly one of"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_xattr_set_remove_and_undo() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::write(root.join("doc.pdf"), "pdf")?;
+    if xattr::set(root.join("doc.pdf"), "user.classification", b"internal").is_err() {
+        return Ok(()); // filesystem without user xattr support
+    }
+
+    let ops = json!([
+        { "op": "set_xattr", "dst": "doc.pdf", "name": "user.source_url", "value": "https://example.com/doc.pdf" },
+        { "op": "remove_xattr", "dst": "doc.pdf", "name": "user.classification" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    let path = root.join("doc.pdf");
+    assert_eq!(
+        xattr::get(&path, "user.source_url")?,
+        Some(b"https://example.com/doc.pdf".to_vec())
+    );
+    assert_eq!(xattr::get(&path, "user.classification")?, None);
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(xattr::get(&path, "user.source_url")?, None);
+    assert_eq!(
+        xattr::get(&path, "user.classification")?,
+        Some(b"internal".to_vec())
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_xattr_respects_symlink_policy() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::write(root.join("target.txt"), "content")?;
+    std::os::unix::fs::symlink(root.join("target.txt"), root.join("link.txt"))?;
+
+    let ops = json!([
+        { "op": "set_xattr", "dst": "link.txt", "name": "user.tag", "value": "x" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let result = tfs::engine::apply(args);
+    assert!(
+        result
+            .unwrap_err()
+            .to_string()
+            .contains("symlink not allowed")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_exchange_and_undo() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::create_dir(root.join("current"))?;
+    fs::write(root.join("current/version"), "1")?;
+    fs::create_dir(root.join("next"))?;
+    fs::write(root.join("next/version"), "2")?;
+
+    let ops = json!([
+        { "op": "exchange", "a": "current", "b": "next" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(fs::read_to_string(root.join("current/version"))?, "2");
+    assert_eq!(fs::read_to_string(root.join("next/version"))?, "1");
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(fs::read_to_string(root.join("current/version"))?, "1");
+    assert_eq!(fs::read_to_string(root.join("next/version"))?, "2");
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_undo_recovers_interrupted_exchange() -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+    let a = root.join("current");
+    let b = root.join("next");
+    let temp = root.join(".current.tfs-exchange");
+    fs::write(&a, "1")?;
+    fs::write(&b, "2")?;
+    let original_inode = fs::symlink_metadata(&a)?.ino();
+
+    // The pending entry is all a crash after step 2 of the fallback leaves.
+    let mut writer = tfs::journal::JournalWriter::open(journal_path.clone())?;
+    writer.write(&tfs::journal::JournalEntry {
+        id: "swap".to_string(),
+        label: None,
+        ts: chrono::Utc::now(),
+        op: "Exchange".to_string(),
+        src: Some(a.clone()),
+        dst: Some(b.clone()),
+        collision: None,
+        status: tfs::journal::JournalStatus::Start,
+        undo: Some(tfs::journal::UndoMetadata::Exchange {
+            a: a.clone(),
+            b: b.clone(),
+            fallback_temp: Some(temp.clone()),
+            original_inode: Some(original_inode),
+        }),
+    })?;
+    drop(writer);
+    fs::rename(&a, &temp)?;
+    fs::rename(&b, &a)?;
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    assert_eq!(tfs::engine::undo(undo_args)?, 0);
+
+    assert_eq!(fs::read_to_string(&a)?, "1");
+    assert_eq!(fs::read_to_string(&b)?, "2");
+    assert!(!temp.exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_rmdir_and_undo_recreates_attributes() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::create_dir(root.join("old"))?;
+    fs::set_permissions(root.join("old"), fs::Permissions::from_mode(0o750))?;
+    let mtime = filetime::FileTime::from_unix_time(1_500_000_000, 42);
+    filetime::set_file_times(root.join("old"), mtime, mtime)?;
+
+    let ops = json!([
+        { "op": "rmdir", "dst": "old" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+    assert!(!root.join("old").exists());
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    let meta = fs::metadata(root.join("old"))?;
+    assert!(meta.is_dir());
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o750);
+    assert_eq!(
+        filetime::FileTime::from_last_modification_time(&meta),
+        mtime
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_rmdir_non_empty_fails_preflight() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::create_dir(root.join("full"))?;
+    fs::write(root.join("full/keep.txt"), "data")?;
+
+    let ops = json!([
+        { "op": "rmdir", "dst": "full" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let result = tfs::engine::apply(args);
+    assert!(result.unwrap_err().to_string().contains("not empty"));
+    assert!(root.join("full/keep.txt").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_rmdir_after_moving_files_out() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::create_dir(root.join("d"))?;
+    fs::write(root.join("d/f"), "data")?;
+
+    let ops = json!([
+        { "op": "move", "src": "d/f", "dst": "f" },
+        { "op": "rmdir", "dst": "d" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+    assert!(!root.join("d").exists());
+    assert_eq!(fs::read_to_string(root.join("f"))?, "data");
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+    assert_eq!(fs::read_to_string(root.join("d/f"))?, "data");
+    assert!(!root.join("f").exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_install_replaces_with_backup_and_undo() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::create_dir(root.join("bin"))?;
+    fs::write(root.join("bin/tool.sh"), "v1")?;
+    fs::write(root.join("tool.new"), "v2")?;
+
+    let ops = json!([
+        { "op": "install", "src": "tool.new", "dst": "bin/tool.sh", "mode": "0755" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: Some(CollisionPolicy::OverwriteWithBackup),
+        root: Some(root.clone()),
+        allow_overwrite: true,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(fs::read_to_string(root.join("bin/tool.sh"))?, "v2");
+    assert_eq!(
+        fs::metadata(root.join("bin/tool.sh"))?.permissions().mode() & 0o7777,
+        0o755
+    );
+    assert_eq!(fs::read_to_string(root.join("bin/tool.sh.backup"))?, "v1");
+    assert_eq!(fs::read_to_string(root.join("tool.new"))?, "v2");
+    // No temp files are left behind.
+    assert_eq!(fs::read_dir(root.join("bin"))?.count(), 2);
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(fs::read_to_string(root.join("bin/tool.sh"))?, "v1");
+    assert!(!root.join("bin/tool.sh.backup").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_install_respects_fail_policy() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::write(root.join("app.conf"), "old")?;
+    fs::write(root.join("app.conf.new"), "new")?;
+
+    let ops = json!([
+        { "op": "install", "src": "app.conf.new", "dst": "app.conf" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_ne!(exit_code, 0);
+    assert_eq!(fs::read_to_string(root.join("app.conf"))?, "old");
+
+    Ok(())
+}
+
+fn append_tar_file<W: std::io::Write>(builder: &mut tar::Builder<W>, path: &str, data: &[u8]) {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_entry_type(tar::EntryType::Regular);
+    // Write the name directly so unsafe paths can be crafted for tests.
+    let name = &mut header.as_old_mut().name;
+    name[..path.len()].copy_from_slice(path.as_bytes());
+    header.set_cksum();
+    builder.append(&header, data).unwrap();
+}
+
+#[test]
+fn test_extract_and_undo() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    let archive = fs::File::create(root.join("vendor.tar.gz"))?;
+    let encoder = flate2::write::GzEncoder::new(archive, flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    append_tar_file(&mut builder, "vendor-1.0/README", b"readme");
+    append_tar_file(&mut builder, "vendor-1.0/src/lib.c", b"int x;");
+    builder.into_inner()?.finish()?;
+
+    fs::create_dir(root.join("third_party"))?;
+    fs::write(root.join("third_party/existing.txt"), "keep")?;
+
+    let ops = json!([
+        { "op": "extract", "archive": "vendor.tar.gz", "dst": "third_party", "strip_components": 1 }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(
+        fs::read_to_string(root.join("third_party/README"))?,
+        "readme"
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("third_party/src/lib.c"))?,
+        "int x;"
+    );
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    assert!(!root.join("third_party/README").exists());
+    assert!(!root.join("third_party/src").exists());
+    assert_eq!(
+        fs::read_to_string(root.join("third_party/existing.txt"))?,
+        "keep"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_extract_rejects_unsafe_members() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    fs::create_dir(root.join("inner"))?;
+
+    let archive = fs::File::create(root.join("evil.tar"))?;
+    let mut builder = tar::Builder::new(archive);
+    append_tar_file(&mut builder, "ok.txt", b"fine");
+    append_tar_file(&mut builder, "../escape.txt", b"evil");
+    builder.finish()?;
+
+    let ops = json!([
+        { "op": "extract", "archive": "evil.tar", "dst": "inner/out" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let result = tfs::engine::apply(args);
+    assert!(
+        result
+            .unwrap_err()
+            .to_string()
+            .contains("unsafe archive member")
+    );
+    assert!(!root.join("inner/out").exists());
+    assert!(!root.join("inner/escape.txt").exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_archive_skips_symlinks_and_undo() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::create_dir_all(root.join("config/nested"))?;
+    fs::write(root.join("config/app.toml"), "name = \"app\"")?;
+    fs::write(root.join("config/nested/db.toml"), "port = 5432")?;
+    fs::write(root.join("notes.txt"), "notes")?;
+    fs::write(root.join("unlisted.txt"), "left out")?;
+    std::os::unix::fs::symlink(root.join("notes.txt"), root.join("config/link.txt"))?;
+
+    let manifest = root.join("plan.json");
+    let plan = json!({
+        "root": root.to_str().unwrap(),
+        "symlink_policy": "skip",
+        "operations": [
+            {
+                "op": "archive",
+                "srcs": ["config", "notes.txt"],
+                "dst": "snapshot.tar.gz",
+                "compression": "gzip"
+            }
+        ]
+    });
+    fs::write(&manifest, plan.to_string())?;
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    let archive = fs::File::open(root.join("snapshot.tar.gz"))?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+    let mut members = Vec::new();
+    for entry in archive.entries()? {
+        members.push(
+            entry?
+                .path()?
+                .to_string_lossy()
+                .trim_end_matches('/')
+                .to_string(),
+        );
+    }
+    assert_eq!(
+        members,
+        [
+            "config",
+            "config/app.toml",
+            "config/nested",
+            "config/nested/db.toml",
+            "notes.txt"
+        ]
+    );
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+    assert!(!root.join("snapshot.tar.gz").exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_archive_nested_symlink_error_rolls_back() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::create_dir(root.join("data"))?;
+    fs::write(root.join("data/a.txt"), "a")?;
+    std::os::unix::fs::symlink(root.join("data/a.txt"), root.join("data/b.txt"))?;
+
+    let ops = json!([
+        { "op": "mkdir", "dst": "backups" },
+        { "op": "archive", "srcs": ["data"], "dst": "backups/data.tar" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 3);
+    // The temp file is discarded and the earlier mkdir rolled back.
+    assert!(!root.join("backups").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_assert_mismatch_fails_preflight() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    fs::write(root.join("config.toml"), "hello")?;
+
+    let ops = json!([
+        { "op": "mkdir", "dst": "out" },
+        {
+            "op": "assert",
+            "path": "config.toml",
+            "kind": "file",
+            "size": 3,
+            "sha256": "0000000000000000000000000000000000000000000000000000000000000000"
+        }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let err = tfs::engine::apply(args).unwrap_err();
+    assert_eq!(tfs::exit_codes::for_error(&err), 2);
+    let failed = err
+        .downcast_ref::<tfs::assertion::AssertionFailed>()
+        .unwrap();
+    let properties: Vec<&str> = failed
+        .mismatches
+        .iter()
+        .map(|m| m.property.as_str())
+        .collect();
+    assert_eq!(properties, ["size", "sha256"]);
+    assert_eq!(failed.mismatches[0].actual, "5");
+    assert!(!root.join("out").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_assert_mismatch_in_preflight_reports_json_event() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    fs::write(root.join("config.toml"), "hello")?;
+
+    let ops = json!([
+        { "op": "mkdir", "dst": "out" },
+        { "op": "assert", "path": "config.toml", "size": 3, "id": "config", "label": "stale config" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_tfs"))
+        .arg("apply")
+        .arg("--manifest")
+        .arg(manifest)
+        .arg("--json")
+        .output()?;
+    assert_eq!(output.status.code(), Some(2));
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let events: Vec<serde_json::Value> = stdout
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let failed = events
+        .iter()
+        .find(|e| e["type"] == "assertion_failed")
+        .expect("assertion_failed event");
+    assert_eq!(failed["op_id"], "config");
+    assert_eq!(failed["label"], "stale config");
+    assert_eq!(
+        failed["mismatches"],
+        json!([{ "property": "size", "expected": "3", "actual": "5" }])
+    );
+    assert!(!root.join("out").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_assert_rechecked_during_execution() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    let hello_sha256 = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    let ops = json!([
+        { "op": "write_file", "dst": "greeting.txt", "content": "hello" },
+        { "op": "assert", "path": "greeting.txt", "size": 5, "sha256": hello_sha256 },
+        { "op": "assert", "path": "missing.txt", "exists": false }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 0);
+    fs::remove_file(root.join("greeting.txt"))?;
+
+    // The written content no longer matches, so the whole transaction rolls back.
+    let ops = json!([
+        { "op": "write_file", "dst": "greeting.txt", "content": "goodbye" },
+        { "op": "assert", "path": "greeting.txt", "sha256": hello_sha256 }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 2);
+    assert!(!root.join("greeting.txt").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_move_into_expands_per_file_and_undo() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::create_dir(root.join("Archive"))?;
+    fs::write(root.join("a.txt"), "a")?;
+    fs::write(root.join("b.txt"), "b")?;
+    fs::create_dir(root.join("old"))?;
+    fs::write(root.join("old/a.txt"), "old a")?;
+
+    let ops = json!([
+        { "op": "move_into", "srcs": ["a.txt", "b.txt", "old/a.txt"], "dir": "Archive/2025", "create_dir": true }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: Some(tfs::model::CollisionPolicy::Suffix),
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(fs::read_to_string(root.join("Archive/2025/a.txt"))?, "a");
+    assert_eq!(fs::read_to_string(root.join("Archive/2025/b.txt"))?, "b");
+    // The second a.txt collides with the first and is handled on its own.
+    assert_eq!(
+        fs::read_to_string(root.join("Archive/2025/a.txt.2"))?,
+        "old a"
+    );
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(fs::read_to_string(root.join("a.txt"))?, "a");
+    assert_eq!(fs::read_to_string(root.join("b.txt"))?, "b");
+    assert_eq!(fs::read_to_string(root.join("old/a.txt"))?, "old a");
+    assert!(!root.join("Archive/2025").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_rename_batch_and_undo() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::write(root.join("IMG_1234.JPG"), "first")?;
+    fs::write(root.join("IMG_1235.JPG"), "second")?;
+
+    let ops = json!([
+        {
+            "op": "rename_batch",
+            "srcs": ["IMG_1234.JPG", "IMG_1235.JPG"],
+            "pattern": "^IMG_(\\d+)\\.JPG$",
+            "replacement": "2025-01-02_$1.jpg"
+        }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+    assert_eq!(
+        fs::read_to_string(root.join("2025-01-02_1234.jpg"))?,
+        "first"
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("2025-01-02_1235.jpg"))?,
+        "second"
+    );
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+    assert_eq!(fs::read_to_string(root.join("IMG_1234.JPG"))?, "first");
+    assert_eq!(fs::read_to_string(root.join("IMG_1235.JPG"))?, "second");
+
+    Ok(())
+}
+
+#[test]
+fn test_rename_batch_rejects_conflicting_targets() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::write(root.join("a1.txt"), "1")?;
+    fs::write(root.join("a2.txt"), "2")?;
+    fs::write(root.join("b.txt"), "b")?;
+    fs::write(root.join("x.txt"), "x")?;
+    fs::write(root.join("x.txt.txt"), "xx")?;
+
+    let cases = [
+        // Both sources map to `a.txt`.
+        (
+            json!(["a1.txt", "a2.txt"]),
+            "^a\\d",
+            "a",
+            "same destination",
+        ),
+        // `x.txt.txt` -> `x.txt` would land on a source renamed later.
+        (
+            json!(["x.txt.txt", "x.txt"]),
+            "\\.txt$",
+            "",
+            "collides with later source",
+        ),
+    ];
+    for (srcs, pattern, replacement, message) in cases {
+        let ops = json!([
+            { "op": "rename", "src": "b.txt", "dst": "c.txt" },
+            { "op": "rename_batch", "srcs": srcs, "pattern": pattern, "replacement": replacement }
+        ]);
+        let manifest = create_manifest(&root, ops);
+
+        let args = ApplyArgs {
+            manifest,
+            validate_only: false,
+            dry_run: false,
+            json: false,
+            journal: None,
+            collision_policy: None,
+            root: Some(root.clone()),
+            allow_overwrite: false,
+            format: None,
+        };
+
+        let err = tfs::engine::apply(args).unwrap_err();
+        assert!(err.to_string().contains(message), "{err}");
+        assert!(root.join("b.txt").exists());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_edit_with_rename_and_undo() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::create_dir(root.join("src"))?;
+    fs::write(root.join("src/old_mod.rs"), "pub fn f() {}\n")?;
+    fs::write(
+        root.join("src/lib.rs"),
+        "mod old_mod;\npub use old_mod::f;\n",
+    )?;
+
+    let ops = json!([
+        { "op": "rename", "src": "src/old_mod.rs", "dst": "src/new_mod.rs" },
+        { "op": "edit", "dst": "src/lib.rs", "find": "old_mod", "replace": "new_mod", "count": 2 },
+        { "op": "edit", "dst": "src/lib.rs", "find": "pub use", "replace": "pub(crate) use" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+    assert!(root.join("src/new_mod.rs").exists());
+    assert_eq!(
+        fs::read_to_string(root.join("src/lib.rs"))?,
+        "mod new_mod;\npub(crate) use new_mod::f;\n"
+    );
+    // Each edit keeps its own backup of the file it replaced.
+    assert_eq!(
+        fs::read_to_string(root.join("src/lib.rs.backup"))?,
+        "mod old_mod;\npub use old_mod::f;\n"
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("src/lib.rs.1.backup"))?,
+        "mod new_mod;\npub use new_mod::f;\n"
+    );
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+    assert!(root.join("src/old_mod.rs").exists());
+    assert_eq!(
+        fs::read_to_string(root.join("src/lib.rs"))?,
+        "mod old_mod;\npub use old_mod::f;\n"
+    );
+    assert!(!root.join("src/lib.rs.backup").exists());
+    assert!(!root.join("src/lib.rs.1.backup").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_edit_match_count_mismatch_fails_preflight() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    fs::write(root.join("a.txt"), "a")?;
+    fs::write(root.join("Makefile"), "CC=gcc\n")?;
+
+    let ops = json!([
+        { "op": "rename", "src": "a.txt", "dst": "b.txt" },
+        { "op": "edit", "dst": "Makefile", "find": "CC=(\\w+)", "replace": "CC=$1-12", "regex": true, "count": 2 }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let err = tfs::engine::apply(args).unwrap_err();
+    assert!(err.to_string().contains("expected 2 matches"), "{err}");
+    assert!(root.join("a.txt").exists());
+    assert_eq!(fs::read_to_string(root.join("Makefile"))?, "CC=gcc\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_edit_rolled_back_on_later_failure() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    fs::write(root.join("Makefile"), "CC=gcc\n")?;
+    fs::write(root.join("exists.txt"), "x")?;
+
+    let ops = json!([
+        { "op": "edit", "dst": "Makefile", "find": "CC=(\\w+)", "replace": "CC=$1-12", "regex": true },
+        { "op": "write_file", "dst": "exists.txt", "content": "clash" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 3);
+    assert_eq!(fs::read_to_string(root.join("Makefile"))?, "CC=gcc\n");
+    assert!(!root.join("Makefile.backup").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_per_op_collision_override_and_cli_precedence() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    let ops = json!([
+        { "op": "copy", "src": "a.txt", "dst": "b.txt", "collision_policy": "overwrite_with_backup" },
+        { "op": "copy", "src": "a.txt", "dst": "c.txt" },
+        { "op": "assert", "path": "a.txt", "collision_policy": "hash8" }
+    ]);
+
+    // Plan default `fail`, op override wins for the first copy.
+    fs::write(root.join("a.txt"), "new")?;
+    fs::write(root.join("b.txt"), "old")?;
+    let journal_path = root.join("op.jsonl");
+    let args = ApplyArgs {
+        manifest: create_manifest(&root, ops.clone()),
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: true,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 0);
+    assert_eq!(fs::read_to_string(root.join("b.txt"))?, "new");
+    assert_eq!(fs::read_to_string(root.join("b.txt.backup"))?, "old");
+
+    let policies: Vec<_> = tfs::journal::read_journal(journal_path)?
+        .into_iter()
+        .filter(|e| e.status == tfs::journal::JournalStatus::Ok)
+        .map(|e| e.collision.unwrap().policy)
+        .collect();
+    assert_eq!(
+        policies,
+        [
+            tfs::model::CollisionPolicy::OverwriteWithBackup,
+            tfs::model::CollisionPolicy::Fail,
+            // Recorded even for an operation that creates nothing.
+            tfs::model::CollisionPolicy::Hash8
+        ]
+    );
+
+    // A CLI policy beats the op override.
+    fs::remove_file(root.join("c.txt"))?;
+    fs::remove_file(root.join("b.txt.backup"))?;
+    let journal_path = root.join("cli.jsonl");
+    let args = ApplyArgs {
+        manifest: create_manifest(&root, ops),
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: Some(tfs::model::CollisionPolicy::Suffix),
+        root: Some(root.clone()),
+        allow_overwrite: true,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 0);
+    assert_eq!(fs::read_to_string(root.join("b.txt.2"))?, "new");
+    assert!(!root.join("b.txt.backup").exists());
+
+    let first = tfs::journal::read_journal(journal_path)?
+        .into_iter()
+        .find(|e| e.status == tfs::journal::JournalStatus::Ok)
+        .unwrap();
+    assert_eq!(
+        first.collision.unwrap().policy,
+        tfs::model::CollisionPolicy::Suffix
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_per_op_symlink_policy_override() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::write(root.join("target.txt"), "content")?;
+    std::os::unix::fs::symlink(root.join("target.txt"), root.join("link.txt"))?;
+
+    // The plan default (`error`) would reject the symlink.
+    let ops = json!([
+        { "op": "copy", "src": "link.txt", "dst": "copy.txt", "symlink_policy": "follow" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 0);
+    assert_eq!(fs::read_to_string(root.join("copy.txt"))?, "content");
+
+    Ok(())
+}
+
+#[test]
+fn test_operation_ids_and_labels_reach_journal() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::write(root.join("a.txt"), "a")?;
+    fs::write(root.join("b.txt"), "b")?;
+
+    let ops = json!([
+        { "op": "mkdir", "dst": "docs", "id": "make-docs", "label": "Create docs dir" },
+        { "op": "move_into", "srcs": ["a.txt", "b.txt"], "dir": "docs", "id": "file-docs" },
+        { "op": "write_file", "dst": "docs/index.md", "content": "# Docs" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 0);
+
+    let entries: Vec<_> = tfs::journal::read_journal(journal_path)?
+        .into_iter()
+        .filter(|e| e.status == tfs::journal::JournalStatus::Ok)
+        .collect();
+    let ids: Vec<&str> = entries.iter().map(|e| e.id.as_str()).collect();
+    assert_eq!(
+        &ids[..3],
+        ["make-docs", "file-docs:a.txt", "file-docs:b.txt"]
+    );
+    // Operations without an id are named by their manifest position.
+    assert_eq!(ids[3], "#3");
+    assert_eq!(entries[0].label.as_deref(), Some("Create docs dir"));
+    assert_eq!(entries[1].label, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_duplicate_operation_ids_rejected() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    let ops = json!([
+        { "op": "mkdir", "dst": "a", "id": "step" },
+        { "op": "mkdir", "dst": "b", "id": "step" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: true,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+    let err = tfs::engine::apply(args).unwrap_err();
+    assert!(err.to_string().contains("duplicate operation id: step"));
+
+    Ok(())
+}
+
+#[test]
+fn test_after_reorders_execution() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::write(root.join("a.txt"), "a")?;
+
+    let ops = json!([
+        { "op": "copy", "src": "a.txt", "dst": "b.txt", "id": "copy-b", "after": ["copy-c"] },
+        { "op": "copy", "src": "a.txt", "dst": "c.txt", "id": "copy-c" },
+        { "op": "mkdir", "dst": "out", "id": "make-out" },
+        { "op": "copy", "src": "a.txt", "dst": "out/a.txt", "id": "copy-out" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 0);
+
+    let ids: Vec<String> = tfs::journal::read_journal(journal_path)?
+        .into_iter()
+        .filter(|e| e.status == tfs::journal::JournalStatus::Ok)
+        .map(|e| e.id)
+        .collect();
+    assert_eq!(ids, ["copy-c", "copy-b", "make-out", "copy-out"]);
+    assert_eq!(fs::read_to_string(root.join("out/a.txt"))?, "a");
+
+    Ok(())
+}
+
+#[test]
+fn test_dependency_cycles_and_unknown_ids_rejected() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::write(root.join("a.txt"), "a")?;
+
+    let validate = |ops: serde_json::Value| {
+        let args = ApplyArgs {
+            manifest: create_manifest(&root, ops),
+            validate_only: true,
+            dry_run: false,
+            json: false,
+            journal: None,
+            collision_policy: None,
+            root: Some(root.clone()),
+            allow_overwrite: false,
+            format: None,
+        };
+        tfs::engine::apply(args)
+    };
+
+    // Explicit cycle.
+    let err = validate(json!([
+        { "op": "mkdir", "dst": "x", "id": "x", "after": ["y"] },
+        { "op": "mkdir", "dst": "y", "id": "y", "after": ["x"] }
+    ]))
+    .unwrap_err();
+    assert!(err.to_string().contains("dependency cycle"), "{err}");
+
+    let err = validate(json!([
+        { "op": "mkdir", "dst": "x", "after": ["missing"] }
+    ]))
+    .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("unknown dependency \"missing\" of #1"),
+        "{err}"
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_ops_on_earlier_destinations_apply_and_undo() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::write(root.join("a.txt"), "a")?;
+    fs::write(root.join("x.txt"), "x")?;
+
+    // Each op works on a path only an earlier op creates.
+    let ops = json!([
+        { "op": "move", "src": "x.txt", "dst": "y.txt" },
+        { "op": "move", "src": "y.txt", "dst": "z.txt" },
+        { "op": "mkdir", "dst": "d" },
+        { "op": "copy", "src": "a.txt", "dst": "d/a.txt" },
+        { "op": "chmod", "dst": "d/a.txt", "mode": "0600" }
+    ]);
+    let manifest = create_manifest(&root, ops);
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+    let exit_code = tfs::engine::apply(args)?;
+    assert_eq!(exit_code, 0);
+    assert_eq!(fs::read_to_string(root.join("z.txt"))?, "x");
+    assert!(!root.join("y.txt").exists());
+    assert_eq!(
+        fs::metadata(root.join("d/a.txt"))?.permissions().mode() & 0o777,
+        0o600
+    );
+
+    let undo_args = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    let exit_code = tfs::engine::undo(undo_args)?;
+    assert_eq!(exit_code, 0);
+    assert_eq!(fs::read_to_string(root.join("x.txt"))?, "x");
+    assert!(!root.join("z.txt").exists());
+    assert!(!root.join("d").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_manifest_vars_expand_in_journal() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+    let manifest = root.join("plan.json");
+
+    fs::write(root.join("a.txt"), "a")?;
+    fs::write(
+        &manifest,
+        json!({
+            "root": root.to_str().unwrap(),
+            "vars": { "client": "Projects/2026/ClientX" },
+            "operations": [
+                { "op": "mkdir", "dst": "${client}", "parents": true },
+                { "op": "move", "src": "a.txt", "dst": "${client}/a.txt" }
+            ]
+        })
+        .to_string(),
+    )?;
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 0);
+    assert!(root.join("Projects/2026/ClientX/a.txt").exists());
+
+    let entries = tfs::journal::read_journal(journal_path)?;
+    // The journal records the expanded destination.
+    assert!(entries.iter().any(|e| {
+        e.status == tfs::journal::JournalStatus::Ok
+            && e.dst
+                .as_ref()
+                .is_some_and(|dst| dst.ends_with("Projects/2026/ClientX/a.txt"))
+    }));
+
+    Ok(())
+}
+
+#[test]
+fn test_yaml_and_toml_manifests() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::write(root.join("a.txt"), "a")?;
+    let yaml = root.join("plan.yaml");
+    fs::write(
+        &yaml,
+        format!(
+            "root: {}\noperations:\n  # Hand-written.\n  - {{ op: mkdir, dst: docs }}\n  - {{ op: move, src: a.txt, dst: docs/a.txt }}\n",
+            root.display()
+        ),
+    )?;
+    // TOML with an extension that does not imply a format.
+    let toml = root.join("plan.manifest");
+    fs::write(
+        &toml,
+        format!(
+            "root = {:?}\n\n[[operations]]\nop = \"copy\"\nsrc = \"docs/a.txt\"\ndst = \"b.txt\"\n",
+            root.display().to_string()
+        ),
+    )?;
+
+    let args = |manifest: PathBuf, format| ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.clone()),
+        allow_overwrite: false,
+        format,
+    };
+    assert_eq!(tfs::engine::apply(args(yaml, None))?, 0);
+    assert!(root.join("docs/a.txt").exists());
+
+    // Without --format the file is parsed as JSON and rejected.
+    assert!(tfs::engine::apply(args(toml.clone(), None)).is_err());
+    assert_eq!(
+        tfs::engine::apply(args(toml, Some(tfs::model::ManifestFormat::Toml)))?,
+        0
+    );
+    assert_eq!(fs::read_to_string(root.join("b.txt"))?, "a");
+
+    Ok(())
+}
+
+fn write_ndjson(root: &std::path::Path, transaction: &str, ops: &[serde_json::Value]) -> PathBuf {
+    let path = root.join("plan.ndjson");
+    let mut lines = vec![
+        json!({
+            "root": root.to_str().unwrap(),
+            "transaction": transaction,
+            "vars": { "out": "out" }
+        })
+        .to_string(),
+    ];
+    lines.extend(ops.iter().map(|op| op.to_string()));
+    fs::write(&path, lines.join("\n")).unwrap();
+    path
+}
+
+#[test]
+fn test_ndjson_manifest_streams_in_op_mode() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::write(root.join("a.txt"), "a")?;
+    fs::create_dir(root.join("out"))?;
+    fs::write(root.join("out/taken.txt"), "taken")?;
+
+    let manifest = write_ndjson(
+        &root,
+        "op",
+        &[
+            json!({ "op": "copy", "src": "a.txt", "dst": "${out}/a.txt", "id": "first" }),
+            // Fails at execution; op mode carries on with the next line.
+            json!({ "op": "copy", "src": "a.txt", "dst": "${out}/taken.txt" }),
+            json!({ "op": "copy", "src": "out/a.txt", "dst": "b.txt", "after": ["first"] }),
+        ],
+    );
+
+    // A dry run preflights later lines as if earlier ones had run.
+    let args = ApplyArgs {
+        manifest: manifest.clone(),
+        validate_only: false,
+        dry_run: true,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: None,
+        allow_overwrite: false,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 0);
+    assert!(!root.join("out/a.txt").exists());
+
+    let args = ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: None,
+        allow_overwrite: false,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 0);
+    assert_eq!(fs::read_to_string(root.join("out/taken.txt"))?, "taken");
+    assert_eq!(fs::read_to_string(root.join("b.txt"))?, "a");
+
+    let ok = tfs::journal::read_journal(journal_path.clone())?
+        .into_iter()
+        .filter(|e| e.status == tfs::journal::JournalStatus::Ok)
+        .count();
+    assert_eq!(ok, 2);
+
+    // Undo works from the streamed journal like any other.
+    let undo = UndoArgs {
+        journal: journal_path,
+        json: false,
+        dry_run: false,
+    };
+    assert_eq!(tfs::engine::undo(undo)?, 0);
+    assert!(!root.join("b.txt").exists());
+    assert!(!root.join("out/a.txt").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_ndjson_forward_dependencies() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    let ops = [
+        json!({ "op": "mkdir", "dst": "x", "after": ["later"] }),
+        json!({ "op": "mkdir", "dst": "y", "id": "later" }),
+    ];
+    let args = |manifest: PathBuf| ApplyArgs {
+        manifest,
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: None,
+        allow_overwrite: false,
+        format: None,
+    };
+
+    // Streaming cannot wait for an operation it has not read yet.
+    let err = tfs::engine::apply(args(write_ndjson(&root, "op", &ops))).unwrap_err();
+    assert!(
+        err.to_string().contains("unknown dependency \"later\""),
+        "{err}"
+    );
+    assert!(!root.join("x").exists());
+
+    // In all mode the whole plan is read first, so any order works.
+    assert_eq!(
+        tfs::engine::apply(args(write_ndjson(&root, "all", &ops)))?,
+        0
+    );
+    assert!(root.join("x").is_dir() && root.join("y").is_dir());
+
+    Ok(())
+}
+
+#[test]
+fn test_manifest_from_stdin() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::write(root.join("a.txt"), "a")?;
+    let header = json!({ "root": root.to_str().unwrap(), "transaction": "op" });
+    let op = json!({ "op": "move", "src": "a.txt", "dst": "b.txt" });
+
+    assert_cmd::cargo::cargo_bin_cmd!("tfs")
+        .args(["apply", "--manifest", "-", "--format", "ndjson"])
+        .write_stdin(format!("{}\n{}\n", header, op))
+        .assert()
+        .success();
+    assert!(root.join("b.txt").exists());
+
+    let plan = json!({
+        "root": root.to_str().unwrap(),
+        "operations": [{ "op": "move", "src": "b.txt", "dst": "c.txt" }]
+    });
+    assert_cmd::cargo::cargo_bin_cmd!("tfs")
+        .args(["apply", "--manifest", "-"])
+        .write_stdin(plan.to_string())
+        .assert()
+        .success();
+    assert!(root.join("c.txt").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_includes_splice_operations_and_reach_journal_header() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    let journal_path = root.join("journal.jsonl");
+
+    fs::create_dir(root.join("fragments"))?;
+    fs::write(
+        root.join("fragments/skeleton.yaml"),
+        "version: 2\ninclude: [common.json]\noperations:\n  - { op: mkdir, dst: \"${project}/src\", id: src }\n",
+    )?;
+    fs::write(
+        root.join("fragments/common.json"),
+        json!({ "operations": [{ "op": "mkdir", "dst": "${project}", "id": "project" }] })
+            .to_string(),
+    )?;
+    let manifest = root.join("plan.json");
+    fs::write(
+        &manifest,
+        json!({
+            "version": 2,
+            "root": root.to_str().unwrap(),
+            "vars": { "project": "acme" },
+            // common.json is also reached through skeleton.yaml, and is
+            // spliced only once.
+            "include": ["fragments/skeleton.yaml", "fragments/common.json"],
+            "operations": [{ "op": "write_file", "dst": "acme/src/main.rs", "content": "", "id": "main" }]
+        })
+        .to_string(),
+    )?;
+
+    let args = ApplyArgs {
+        manifest: manifest.clone(),
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: Some(journal_path.clone()),
+        collision_policy: None,
+        root: None,
+        allow_overwrite: false,
+        format: None,
+    };
+    assert_eq!(tfs::engine::apply(args)?, 0);
+    assert!(root.join("acme/src/main.rs").exists());
+
+    let ids: Vec<String> = tfs::journal::read_journal(journal_path.clone())?
+        .into_iter()
+        .filter(|e| e.status == tfs::journal::JournalStatus::Ok)
+        .map(|e| e.id)
+        .collect();
+    assert_eq!(ids, ["project", "src", "main"]);
+
+    let header = tfs::journal::read_journal_header(journal_path)?.expect("journal header");
+    let manifest = manifest.canonicalize()?;
+    let skeleton = root.join("fragments/skeleton.yaml").canonicalize()?;
+    let chain: Vec<(PathBuf, Option<PathBuf>)> = header
+        .sources
+        .iter()
+        .map(|s| (s.path.clone(), s.included_from.clone()))
+        .collect();
+    assert_eq!(
+        chain,
+        [
+            (manifest.clone(), None),
+            (skeleton.clone(), Some(manifest)),
+            (
+                root.join("fragments/common.json").canonicalize()?,
+                Some(skeleton)
+            ),
+        ]
+    );
+    let yaml = fs::read(root.join("fragments/skeleton.yaml"))?;
+    assert_eq!(
+        header.sources[1].blake3.as_deref(),
+        Some(tfs::fsops::content_hash(&yaml).as_str())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_include_cycles_and_root_rejected() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    fs::write(
+        root.join("a.json"),
+        json!({ "version": 2, "include": ["b.json"] }).to_string(),
+    )?;
+    fs::write(
+        root.join("b.json"),
+        json!({ "version": 2, "include": ["a.json"] }).to_string(),
+    )?;
+    fs::write(
+        root.join("rooted.json"),
+        json!({ "root": "/elsewhere", "operations": [] }).to_string(),
+    )?;
+
+    fs::write(
+        root.join("old.json"),
+        json!({ "include": ["rooted.json"] }).to_string(),
+    )?;
+
+    let validate_as = |version: u32, include: &str| {
+        let manifest = root.join("plan.json");
+        fs::write(
+            &manifest,
+            json!({
+                "version": version,
+                "root": root.to_str().unwrap(),
+                "include": [include],
+                "operations": []
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let args = ApplyArgs {
+            manifest,
+            validate_only: true,
+            dry_run: false,
+            json: false,
+            journal: None,
+            collision_policy: None,
+            root: None,
+            allow_overwrite: false,
+            format: None,
+        };
+        tfs::engine::apply(args).unwrap_err()
+    };
+    let validate = |include: &str| validate_as(2, include);
+
+    let err = format!("{:#}", validate("a.json"));
+    assert!(err.contains("include cycle:"), "{err}");
+    assert!(err.contains("a.json -> "), "{err}");
+    let err = format!("{:#}", validate("rooted.json"));
+    assert!(err.contains("included manifest must not set root"), "{err}");
+    let err = format!("{:#}", validate_as(1, "rooted.json"));
+    assert!(err.contains("include requires manifest version 2"), "{err}");
+    let err = format!("{:#}", validate("old.json"));
+    assert!(err.contains("include requires manifest version 2"), "{err}");
+
+    Ok(())
+}
+
+#[test]
+fn test_manifest_diagnostics_in_json_mode() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+
+    let manifest = root.join("plan.json");
+    let text = format!(
+        "{{\n  \"root\": {:?},\n  \"operations\": [\n    {{ \"op\": \"mkdir\", \"dst\": \"a\", \"id\": \"a\" }},\n    {{ \"op\": \"archive\", \"srcs\": [], \"dst\": \"b.tar\", \"id\": \"a\" }},\n    {{ \"op\": \"mkdir\", \"dst\": \"${{missing}}\" }}\n  ]\n}}\n",
+        root.to_str().unwrap()
+    );
+    fs::write(&manifest, text)?;
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_tfs"))
+        .arg("apply")
+        .arg("--manifest")
+        .arg(&manifest)
+        .arg("--validate-only")
+        .arg("--json")
+        .output()?;
+    assert_eq!(output.status.code(), Some(1));
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let diagnostics: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+        .filter(|e| e["type"] == "manifest_diagnostic")
+        .collect();
+    let found: Vec<(&str, &str)> = diagnostics
+        .iter()
+        .map(|d| (d["code"].as_str().unwrap(), d["pointer"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("invalid_operation", "/operations/1/srcs"),
+            ("duplicate_id", "/operations/1/id"),
+            ("invalid_variable", "/operations/2"),
+        ]
+    );
+    assert_eq!(diagnostics[1]["line"], 5);
+    assert_eq!(diagnostics[1]["column"], 58);
+    assert!(diagnostics.iter().all(|d| d["hint"].is_string()));
+
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("duplicate operation id: a"), "{stderr}");
+    assert!(stderr.contains("undefined variable: missing"), "{stderr}");
+
+    let diagnose = |manifest: &std::path::Path| -> Result<Vec<serde_json::Value>> {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_tfs"))
+            .arg("apply")
+            .arg("--manifest")
+            .arg(manifest)
+            .arg("--validate-only")
+            .arg("--json")
+            .output()?;
+        assert_eq!(output.status.code(), Some(1));
+        Ok(String::from_utf8(output.stdout)?
+            .lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+            .filter(|e| e["type"] == "manifest_diagnostic")
+            .collect())
+    };
+
+    // A problem only ordering would find is still a diagnostic.
+    let manifest = create_manifest(
+        &root,
+        json!([{ "op": "mkdir", "dst": "a", "after": ["nope"] }]),
+    );
+    let diagnostics = diagnose(&manifest)?;
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["code"], "unknown_dependency");
+    assert_eq!(diagnostics[0]["pointer"], "/operations/0/after/0");
+
+    // NDJSON problems carry their line numbers.
+    let manifest = write_ndjson(
+        &root,
+        "op",
+        &[
+            json!({ "op": "mkdir", "dst": "a", "id": "a" }),
+            json!({ "op": "teleport", "dst": "b" }),
+            json!({ "op": "mkdir", "dst": "c", "id": "a" }),
+        ],
+    );
+    let diagnostics = diagnose(&manifest)?;
+    let found: Vec<(&str, &str, u64)> = diagnostics
+        .iter()
+        .map(|d| {
+            (
+                d["code"].as_str().unwrap(),
+                d["pointer"].as_str().unwrap(),
+                d["line"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("unknown_operation", "/operations/1/op", 3),
+            ("duplicate_id", "/operations/2/id", 4),
+        ]
+    );
+    // Nothing ran: the whole file is checked before streaming.
+    assert!(!root.join("a").exists());
+
+    Ok(())
+}
+
+/// Apply `ops` under `root` with default settings, returning the exit code.
+fn apply_ops(root: &std::path::Path, ops: serde_json::Value) -> Result<i32> {
+    let args = ApplyArgs {
+        manifest: create_manifest(root, ops),
+        validate_only: false,
+        dry_run: false,
+        json: false,
+        journal: None,
+        collision_policy: None,
+        root: Some(root.to_path_buf()),
+        allow_overwrite: false,
+        format: None,
+    };
+    tfs::engine::apply(args)
+}
+
+#[test]
+fn test_move_into_a_file_written_earlier() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    fs::create_dir(root.join("docs"))?;
+
+    let ops = json!([
+        { "op": "write_file", "dst": "a.txt", "content": "a" },
+        { "op": "move_into", "srcs": ["a.txt"], "dir": "docs" }
+    ]);
+    assert_eq!(apply_ops(root, ops)?, 0);
+    assert_eq!(fs::read_to_string(root.join("docs/a.txt"))?, "a");
+
+    Ok(())
+}
+
+#[test]
+fn test_hardlink_a_file_written_earlier() -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempdir()?;
+    let root = dir.path();
+
+    let ops = json!([
+        { "op": "write_file", "dst": "a.txt", "content": "a" },
+        { "op": "hardlink", "src": "a.txt", "dst": "b.txt" }
+    ]);
+    assert_eq!(apply_ops(root, ops)?, 0);
+    assert_eq!(
+        fs::metadata(root.join("a.txt"))?.ino(),
+        fs::metadata(root.join("b.txt"))?.ino()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_exchange_with_a_file_written_earlier() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    fs::write(root.join("a.txt"), "a")?;
+
+    let ops = json!([
+        { "op": "write_file", "dst": "b.txt", "content": "b" },
+        { "op": "exchange", "a": "a.txt", "b": "b.txt" }
+    ]);
+    assert_eq!(apply_ops(root, ops)?, 0);
+    assert_eq!(fs::read_to_string(root.join("a.txt"))?, "b");
+    assert_eq!(fs::read_to_string(root.join("b.txt"))?, "a");
+
+    Ok(())
+}
+
+#[test]
+fn test_rename_batch_over_files_written_earlier() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path();
+
+    let ops = json!([
+        { "op": "write_file", "dst": "a.txt", "content": "a" },
+        { "op": "rename_batch", "srcs": ["a.txt"], "pattern": "\\.txt$", "replacement": ".md" }
+    ]);
+    assert_eq!(apply_ops(root, ops)?, 0);
+    assert_eq!(fs::read_to_string(root.join("a.md"))?, "a");
+    assert!(!root.join("a.txt").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_rmdir_a_directory_made_earlier() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path();
+
+    let ops = json!([
+        { "op": "mkdir", "dst": "scratch" },
+        { "op": "rmdir", "dst": "scratch" }
+    ]);
+    assert_eq!(apply_ops(root, ops)?, 0);
+    assert!(!root.join("scratch").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_extract_an_archive_written_earlier() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    fs::create_dir(root.join("data"))?;
+    fs::write(root.join("data/a.txt"), "a")?;
+
+    let ops = json!([
+        { "op": "archive", "srcs": ["data"], "dst": "data.tar" },
+        { "op": "extract", "archive": "data.tar", "dst": "restored" }
+    ]);
+    assert_eq!(apply_ops(root, ops)?, 0);
+    assert_eq!(fs::read_to_string(root.join("restored/data/a.txt"))?, "a");
+
+    Ok(())
+}
+
+#[test]
+fn test_move_into_create_dir_made_earlier() -> Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    fs::write(root.join("a.txt"), "a")?;
+
+    // The directory exists by the time move_into runs, so it must not
+    // expand to a second mkdir of it.
+    let ops = json!([
+        { "op": "mkdir", "dst": "out" },
+        { "op": "move_into", "srcs": ["a.txt"], "dir": "out", "create_dir": true }
+    ]);
+    assert_eq!(apply_ops(root, ops)?, 0);
+    assert_eq!(fs::read_to_string(root.join("out/a.txt"))?, "a");
+
+    Ok(())
+}